@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use clap::{Parser, Subcommand};
 
 use crate::*;
@@ -6,6 +8,15 @@ use crate::*;
 pub struct Opt {
     #[command(subcommand)]
     pub subcommand: Commands,
+    /// print planned file moves without changing files
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    /// append moved and created files to journal, for 'undo'
+    #[arg(long, global = true)]
+    pub journal: Option<PathBuf>,
+    /// policy for existing destination files when moving, copying or linking
+    #[arg(long, global = true, value_enum, default_value = "rename")]
+    pub on_conflict: journal::OnConflict,
 }
 
 #[derive(Subcommand, Clone, Debug)]
@@ -20,8 +31,13 @@ pub enum Commands {
     },
     Cmds(cmds::Opt),
     Convert(convert::Opt),
+    IsAnimated(is_animated::Opt),
     IsApng(is_apng::Opt),
+    JpegInfo(jpegquality::Opt),
+    Png(png_chunks::Opt),
     ShellCompletions,
+    /// revert file moves and created files from journal
+    Undo(journal::UndoOpt),
 }
 
 #[derive(Subcommand, Clone, Debug)]
@@ -31,10 +47,14 @@ pub enum SelectableFind {
     Resizable(find::resizable::Opt),
     Similar(find::similar::Opt),
     Detailed(find::detailed::Opt),
+    JpegQuality(find::jpeg_quality::Opt),
+    MismatchedExt(find::mismatched_ext::Opt),
+    Corrupt(find::corrupt::Opt),
 }
 
 #[derive(Subcommand, Clone, Debug)]
 pub enum SelectableGen {
+    Apng2frames(gen::apng2frames::Opt),
     FfmpegConcat(gen::ffmpeg_concat::Opt),
     Video(gen::video::Opt),
     Zip2video(gen::zip2video::Opt),
@@ -11,7 +11,13 @@ use clap::Args;
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 
-use crate::{csv_output, utils, BResult};
+use crate::{
+    csv_output,
+    metadata::{self, MetadataPolicy},
+    utils,
+    walk::WalkOpt,
+    BResult,
+};
 
 type BytesIO = Vec<u8>;
 
@@ -20,6 +26,8 @@ pub struct Opt {
     /// input image paths
     #[arg(default_value = "./*", display_order = 0)]
     input: Vec<PathBuf>,
+    #[command(flatten)]
+    walk: WalkOpt,
     #[arg(short, default_value = "./out")]
     out_dir: PathBuf,
     /// Commands from json config
@@ -49,6 +57,9 @@ pub struct Opt {
     /// number simultaneously executed cmds for each image
     #[arg(long)]
     nproc_cmd: Option<usize>,
+    /// metadata of saved results
+    #[arg(long, value_enum, default_value = "keep")]
+    metadata: MetadataPolicy,
 }
 
 pub fn main(mut opt: Opt) -> BResult<()> {
@@ -62,7 +73,7 @@ pub fn main(mut opt: Opt) -> BResult<()> {
         opt.tolerance = vec![opt.tolerance[0]; opt.cmds.len()];
     }
 
-    let images = utils::ims_init(&opt.input, &opt.out_dir, opt.nproc_cmd)?;
+    let images = utils::ims_init(&opt.input, &opt.walk, &opt.out_dir, opt.nproc_cmd)?;
 
     // write csv header with cmds
     if opt.csv_save {
@@ -125,10 +136,10 @@ pub fn process_image(
         .cmds
         .par_iter()
         .map(|cmd| {
-            let mut buff = ImageBuffer::new_from_setting(cmd, settings);
+            let mut buff = ImageBuffer::new_from_setting(cmd, settings)?;
             buff.image_generate(img).map(|_| buff)
         })
-        .collect::<Result<_, _>>()?;
+        .collect::<BResult<_>>()?;
 
     if !opt.no_progress {
         println!("{}", &img.display());
@@ -176,8 +187,10 @@ pub fn process_image(
                 i,
                 &buff.extension
             ));
-            let mut f = std::fs::File::create(save_path)?;
+            let mut f = std::fs::File::create(&save_path)?;
             f.write_all(&buff.image)?;
+            drop(f);
+            metadata::apply_policy(opt.metadata, img, &save_path)?;
             continue;
         }
 
@@ -198,7 +211,9 @@ pub fn process_image(
 
     // save res_buf
     if best_filesize == img_filesize {
-        std::fs::copy(img, out_dir.join(img.file_name().unwrap()))?;
+        let save_path = out_dir.join(img.file_name().unwrap());
+        std::fs::copy(img, &save_path)?;
+        metadata::apply_policy(opt.metadata, img, &save_path)?;
         if !opt.no_progress {
             println!("Save: Copy input");
         }
@@ -213,8 +228,10 @@ pub fn process_image(
         &best.extension
     ));
 
-    let mut f = std::fs::File::create(save_path)?;
+    let mut f = std::fs::File::create(&save_path)?;
     f.write_all(&best.image)?;
+    drop(f);
+    metadata::apply_policy(opt.metadata, img, &save_path)?;
     // if !opt.no_progress {
     //     println!("Save: {}", &res_buff.get_cmd());
     // }
@@ -246,25 +263,31 @@ impl ImageBuffer {
         }
     }
 
-    fn new_from_setting(cmd: &str, settings: &HashMap<String, EncodeSetting>) -> Self {
+    pub(crate) fn new_from_setting(
+        cmd: &str,
+        settings: &HashMap<String, EncodeSetting>,
+    ) -> BResult<Self> {
         let (name, args) = match cmd.split_once('(') {
             Some(s) => s,
             None => (cmd, " "),
         };
         let args: Vec<&str> = (args[..args.len() - 1]).split(',').collect();
 
-        let mut setting = settings.get(name).expect("No such setting").clone();
+        let mut setting = settings
+            .get(name)
+            .ok_or_else(|| format!("No such setting: {}", name))?
+            .clone();
 
         for (i, v) in args.iter().enumerate() {
             setting.encode = setting.encode.replace(&format!("%{}%", i + 1), v);
         }
 
-        Self {
+        Ok(Self {
             encoder: setting.encode,
             extension: setting.ext,
             output_from_stdout: setting.output_from_stdout.is_some(),
             ..Default::default()
-        }
+        })
     }
 
     pub fn get_size(&self) -> usize {
@@ -316,10 +339,7 @@ fn command_print_if_error(output: &std::process::Output) -> std::io::Result<()>
     } else {
         let o = [&output.stderr, "\n".as_bytes(), &output.stdout].concat();
         println!("{}", std::str::from_utf8(&o).unwrap());
-        Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "Command returned error status",
-        ))
+        Err(std::io::Error::other("Command returned error status"))
     }
 }
 
@@ -341,15 +361,16 @@ pub struct EncodeSetting {
     output_from_stdout: Option<()>,
 }
 
-fn settings_load(file: &Path) -> BResult<HashMap<String, EncodeSetting>> {
-    if !file.exists() {
-        let mut writer = File::create(file)?;
-        writer.write_all(
-r#"{
+/// Written to new settings file, also used for settings missing in existing file
+const DEFAULT_SETTINGS: &str = r#"{
   "cjxl_d": {
     "encode": "cjxl -d %1% -j 0 --patches=0",
     "ext": "jxl"
   },
+  "cjxl_de": {
+    "encode": "cjxl -d %1% -j 0 -e %2% --patches=0",
+    "ext": "jxl"
+  },
   "cjxl_l": {
     "encode": "cjxl -d 0 -j 0 -e %1% --patches=0",
     "ext": "jxl"
@@ -362,6 +383,10 @@ r#"{
     "encode": "cjxl -d 0 -j 1 -e %1%",
     "ext": "jxl"
   },
+  "cjxl_tr_vardct": {
+    "encode": "cjxl -d 0 -j 1 -m 0 -e %1%",
+    "ext": "jxl"
+  },
   "cjpegli": {
     "encode": "cjpegli -d %1%",
     "ext": "jpg"
@@ -378,14 +403,27 @@ r#"{
     "encode": "avifenc --min 1 --max 63 -d 10 -s %1% -j all -a end-usage=q -a cq-level=%2% -a color:deltaq-mode=3 -a tune=ssim",
     "ext": "avif"
   },
+  "avifenc_q": {
+    "encode": "avifenc --min 0 --max 63 -d 10 -s %1% -j 8 -a end-usage=q -a cq-level=%2% -a color:enable-chroma-deltaq=1 -a color:deltaq-mode=3 -a tune=ssim",
+    "ext": "avif"
+  },
   "avif8_q": {
     "encode": "avifenc --min 1 --max 63 -d 8 -s %1% -j all -a end-usage=q -a cq-level=%2% -a color:deltaq-mode=3 -a tune=butteraugli",
     "ext": "avif"
   }
-}"#.as_bytes()
-        )?;
+}"#;
+
+pub(crate) fn settings_load(file: &Path) -> BResult<HashMap<String, EncodeSetting>> {
+    if !file.exists() {
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut writer = File::create(file)?;
+        writer.write_all(DEFAULT_SETTINGS.as_bytes())?;
     }
     let reader = File::open(file)?;
     let json: HashMap<String, EncodeSetting> = serde_json::from_reader(reader)?;
-    Ok(json)
+    let mut settings: HashMap<String, EncodeSetting> = serde_json::from_str(DEFAULT_SETTINGS)?;
+    settings.extend(json);
+    Ok(settings)
 }
@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+
+use image::DynamicImage;
+use tempfile::NamedTempFile;
+
+use crate::BResult;
+
+use super::color::save_png;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum AlphaKind {
+    /// No alpha channel
+    None,
+    /// All pixels are fully opaque
+    Opaque,
+    /// Pixels are fully opaque or fully transparent
+    Binary,
+    Full,
+}
+
+impl AlphaKind {
+    pub fn has_alpha(&self) -> bool {
+        matches!(self, AlphaKind::Binary | AlphaKind::Full)
+    }
+}
+
+pub(super) fn analyse_alpha(img: &DynamicImage) -> AlphaKind {
+    match img {
+        DynamicImage::ImageLumaA8(b) => classify(b.pixels().map(|p| p.0[1] as u16), u8::MAX as u16),
+        DynamicImage::ImageRgba8(b) => classify(b.pixels().map(|p| p.0[3] as u16), u8::MAX as u16),
+        DynamicImage::ImageLumaA16(b) => classify(b.pixels().map(|p| p.0[1]), u16::MAX),
+        DynamicImage::ImageRgba16(b) => classify(b.pixels().map(|p| p.0[3]), u16::MAX),
+        i if i.color().has_alpha() => AlphaKind::Full,
+        _ => AlphaKind::None,
+    }
+}
+
+fn classify(alpha: impl Iterator<Item = u16>, max: u16) -> AlphaKind {
+    let mut opaque = true;
+    for a in alpha {
+        if a != max {
+            opaque = false;
+            if a != 0 {
+                return AlphaKind::Full;
+            }
+        }
+    }
+    if opaque {
+        AlphaKind::Opaque
+    } else {
+        AlphaKind::Binary
+    }
+}
+
+/// Drop alpha channel of opaque image, or zero colors under fully transparent pixels.
+/// Return path to image for encoding, alpha kind and possible handle to temporary file
+pub(super) fn optimise_alpha(
+    img: DynamicImage,
+    filepath: PathBuf,
+    icc: Option<&[u8]>,
+) -> BResult<(PathBuf, AlphaKind, Option<NamedTempFile>)> {
+    let kind = analyse_alpha(&img);
+    let img = match (kind, img) {
+        (AlphaKind::Opaque, DynamicImage::ImageLumaA8(b)) => {
+            DynamicImage::ImageLumaA8(b).into_luma8().into()
+        }
+        (AlphaKind::Opaque, DynamicImage::ImageRgba8(b)) => {
+            DynamicImage::ImageRgba8(b).into_rgb8().into()
+        }
+        (AlphaKind::Opaque, DynamicImage::ImageLumaA16(b)) => {
+            DynamicImage::ImageLumaA16(b).into_luma16().into()
+        }
+        (AlphaKind::Opaque, DynamicImage::ImageRgba16(b)) => {
+            DynamicImage::ImageRgba16(b).into_rgb16().into()
+        }
+        (AlphaKind::Binary | AlphaKind::Full, mut img) => {
+            if !clean_transparent(&mut img) {
+                return Ok((filepath, kind, None));
+            }
+            img
+        }
+        _ => return Ok((filepath, kind, None)),
+    };
+
+    let tmp = tempfile::Builder::new().suffix(".png").tempfile()?;
+    let tmp_path = tmp.path().to_path_buf();
+    save_png(&img, &tmp_path, icc)?;
+    Ok((tmp_path, kind, Some(tmp)))
+}
+
+/// Zero color channels of fully transparent pixels, return true if any pixel was changed
+fn clean_transparent(img: &mut DynamicImage) -> bool {
+    let mut changed = false;
+    match img {
+        DynamicImage::ImageLumaA8(b) => b.pixels_mut().filter(|p| p.0[1] == 0).for_each(|p| {
+            changed |= p.0[0] != 0;
+            p.0[0] = 0;
+        }),
+        DynamicImage::ImageRgba8(b) => b.pixels_mut().filter(|p| p.0[3] == 0).for_each(|p| {
+            changed |= p.0[..3] != [0; 3];
+            p.0[..3].fill(0);
+        }),
+        DynamicImage::ImageLumaA16(b) => b.pixels_mut().filter(|p| p.0[1] == 0).for_each(|p| {
+            changed |= p.0[0] != 0;
+            p.0[0] = 0;
+        }),
+        DynamicImage::ImageRgba16(b) => b.pixels_mut().filter(|p| p.0[3] == 0).for_each(|p| {
+            changed |= p.0[..3] != [0; 3];
+            p.0[..3].fill(0);
+        }),
+        _ => (),
+    }
+    changed
+}
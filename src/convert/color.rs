@@ -0,0 +1,166 @@
+use std::{io::Write, path::Path};
+
+use flate2::{write::ZlibEncoder, Compression, Crc};
+use image::{DynamicImage, ImageFormat};
+use lcms2::{ColorSpaceSignature, InfoType, Intent, Locale, PixelFormat, Transform};
+
+use crate::BResult;
+
+/// Open image with its embedded icc profile
+pub(super) fn image_open_with_icc(path: &Path) -> BResult<(DynamicImage, Option<Vec<u8>>)> {
+    let mut decoder = image::ImageReader::open(path)?
+        .with_guessed_format()?
+        .into_decoder()?;
+    let icc = image::ImageDecoder::icc_profile(&mut decoder)?;
+    Ok((DynamicImage::from_decoder(decoder)?, icc))
+}
+
+/// Image has more than 8 bits per channel
+pub(super) fn is_high_bit_depth(img: &DynamicImage) -> bool {
+    img.color().bytes_per_pixel() / img.color().channel_count() > 1
+}
+
+/// Grayscale copy of the image keeping alpha and 16-bit depth
+pub(super) fn into_grayscale(img: DynamicImage) -> DynamicImage {
+    match (is_high_bit_depth(&img), img.color().has_alpha()) {
+        (true, true) => DynamicImage::ImageLumaA16(img.into_luma_alpha16()),
+        (true, false) => DynamicImage::ImageLuma16(img.into_luma16()),
+        (false, true) => DynamicImage::ImageLumaA8(img.into_luma_alpha8()),
+        (false, false) => DynamicImage::ImageLuma8(img.into_luma8()),
+    }
+}
+
+/// Save image as png with icc profile if the profile matches image channels.
+/// Float images are saved as 16-bit
+pub(super) fn save_png(img: &DynamicImage, path: &Path, icc: Option<&[u8]>) -> BResult<()> {
+    let img = match img {
+        DynamicImage::ImageRgb32F(_) => &DynamicImage::ImageRgb16(img.to_rgb16()),
+        DynamicImage::ImageRgba32F(_) => &DynamicImage::ImageRgba16(img.to_rgba16()),
+        _ => img,
+    };
+    let mut data = Vec::new();
+    img.write_to(&mut std::io::Cursor::new(&mut data), ImageFormat::Png)?;
+    let gray = img.color().channel_count() <= 2;
+    if let Some(icc) = icc.filter(|icc| icc_is_gray(icc) == Some(gray)) {
+        data = png_insert_iccp(&data, icc)?;
+    }
+    std::fs::write(path, data)?;
+    Ok(())
+}
+
+/// Gray or RGB color space from icc header, None for other spaces
+fn icc_is_gray(icc: &[u8]) -> Option<bool> {
+    match icc.get(16..20)? {
+        b"GRAY" => Some(true),
+        b"RGB " => Some(false),
+        _ => None,
+    }
+}
+
+/// Add iCCP chunk after IHDR of encoded png
+fn png_insert_iccp(png: &[u8], icc: &[u8]) -> BResult<Vec<u8>> {
+    // signature 8 + IHDR length 4 + type 4 + data 13 + crc 4
+    const IHDR_END: usize = 33;
+    if png.len() < IHDR_END || &png[12..16] != b"IHDR" {
+        return Err("Can't find png IHDR chunk".into());
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(icc)?;
+    let mut chunk = b"iCCP".to_vec();
+    chunk.extend_from_slice(b"ICC profile\0\0");
+    chunk.extend_from_slice(&encoder.finish()?);
+    let mut crc = Crc::new();
+    crc.update(&chunk);
+
+    let mut out = png[..IHDR_END].to_vec();
+    out.extend_from_slice(&(chunk.len() as u32 - 4).to_be_bytes());
+    out.extend_from_slice(&chunk);
+    out.extend_from_slice(&crc.sum().to_be_bytes());
+    out.extend_from_slice(&png[IHDR_END..]);
+    Ok(out)
+}
+
+/// Transform pixels from icc profile to sRGB.
+/// Return None if the profile is already sRGB or its color space is not supported
+pub(super) fn convert_to_srgb(img: &DynamicImage, icc: &[u8]) -> BResult<Option<DynamicImage>> {
+    let src = lcms2::Profile::new_icc(icc)?;
+    let description = src
+        .info(InfoType::Description, Locale::none())
+        .unwrap_or_default();
+    if description.contains("sRGB") {
+        return Ok(None);
+    }
+    let high = is_high_bit_depth(img);
+    let alpha = img.color().has_alpha();
+    let (input, in_format, out_format) = match (src.color_space(), high, alpha) {
+        (ColorSpaceSignature::RgbData, false, false) => {
+            (img.to_rgb8().into(), PixelFormat::RGB_8, PixelFormat::RGB_8)
+        }
+        (ColorSpaceSignature::RgbData, false, true) => (
+            img.to_rgba8().into(),
+            PixelFormat::RGBA_8,
+            PixelFormat::RGBA_8,
+        ),
+        (ColorSpaceSignature::RgbData, true, false) => (
+            img.to_rgb16().into(),
+            PixelFormat::RGB_16,
+            PixelFormat::RGB_16,
+        ),
+        (ColorSpaceSignature::RgbData, true, true) => (
+            img.to_rgba16().into(),
+            PixelFormat::RGBA_16,
+            PixelFormat::RGBA_16,
+        ),
+        (ColorSpaceSignature::GrayData, false, false) => (
+            img.to_luma8().into(),
+            PixelFormat::GRAY_8,
+            PixelFormat::RGB_8,
+        ),
+        (ColorSpaceSignature::GrayData, false, true) => (
+            img.to_luma_alpha8().into(),
+            PixelFormat::GRAYA_8,
+            PixelFormat::RGBA_8,
+        ),
+        (ColorSpaceSignature::GrayData, true, false) => (
+            img.to_luma16().into(),
+            PixelFormat::GRAY_16,
+            PixelFormat::RGB_16,
+        ),
+        (ColorSpaceSignature::GrayData, true, true) => (
+            img.to_luma_alpha16().into(),
+            PixelFormat::GRAYA_16,
+            PixelFormat::RGBA_16,
+        ),
+        _ => return Ok(None),
+    };
+    let input: DynamicImage = input;
+
+    let transform = Transform::<u8, u8>::new(
+        &src,
+        in_format,
+        &lcms2::Profile::new_srgb(),
+        out_format,
+        Intent::Perceptual,
+    )?;
+    let (w, h) = (img.width(), img.height());
+    let mut out = vec![0; w as usize * h as usize * out_format.bytes_per_pixel()];
+    transform.transform_pixels(input.as_bytes(), &mut out);
+
+    // 16-bit samples are in native byte order
+    let out16 = || {
+        out.chunks_exact(2)
+            .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+            .collect::<Vec<_>>()
+    };
+    let out = match (high, alpha) {
+        (false, false) => image::RgbImage::from_raw(w, h, out.clone()).map(DynamicImage::from),
+        (false, true) => image::RgbaImage::from_raw(w, h, out.clone()).map(DynamicImage::from),
+        (true, false) => image::ImageBuffer::<image::Rgb<u16>, _>::from_raw(w, h, out16())
+            .map(DynamicImage::from),
+        (true, true) => image::ImageBuffer::<image::Rgba<u16>, _>::from_raw(w, h, out16())
+            .map(DynamicImage::from),
+    };
+    Ok(Some(
+        out.ok_or("Can't build image from transformed pixels")?,
+    ))
+}
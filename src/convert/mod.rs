@@ -0,0 +1,900 @@
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::path::PathBuf;
+
+use clap::{Args, ValueEnum};
+use image::{self, DynamicImage, GenericImageView};
+use rayon::iter::ParallelIterator;
+use rayon::prelude::IntoParallelRefIterator;
+use serde::{Deserialize, Serialize};
+use tempfile::{self, NamedTempFile};
+
+use crate::{
+    cmds::ImageBuffer,
+    find::{action::trash, detailed::image_edge_ratio, monochrome::image_is_monochrome},
+    is_animated, journal,
+    jpegquality::JpegInfo,
+    jxl_header,
+    metadata::{self, isobmff_boxes, Metadata, MetadataPolicy},
+    sniff::ImageFormat,
+    utils, BResult,
+};
+
+use alpha::optimise_alpha;
+use color::{convert_to_srgb, image_open_with_icc, into_grayscale, save_png};
+use profile::{EncodeCandidate, ImageTraits, Profile};
+pub use resize::{ResizeFilter, ResizeFit, ResizeOpt};
+
+mod alpha;
+mod color;
+mod profile;
+mod resize;
+
+#[derive(Args, Debug, Clone)]
+pub struct Opt {
+    #[arg(required_unless_present_any = ["apply_review", "undo"])]
+    input: Option<PathBuf>,
+    #[arg(required_unless_present_any = ["apply_review", "undo"])]
+    output: Option<PathBuf>,
+    /// shorthand for '--profile avif'
+    #[arg(short = 'a', long, conflicts_with = "profile")]
+    avif: bool,
+    /// conversion profile name
+    #[arg(short, long, default_value = "default")]
+    profile: String,
+    /// Path to json file with conversion profiles
+    #[arg(long)]
+    profiles_config_json: Option<PathBuf>,
+    /// Path to json file with cmds config
+    #[arg(long)]
+    cmds_config_json: Option<PathBuf>,
+    #[arg(long)]
+    manga: Option<u8>,
+    /// keep original as '{name}-bak.{ext}', replaced files are always kept
+    #[arg(short = 'r', long)]
+    rename_original: bool,
+    /// remove original after result is written, with global '--journal' move it to trash
+    #[arg(long, conflicts_with = "rename_original")]
+    delete_original: bool,
+    /// metadata of the result
+    #[arg(long, value_enum, default_value = "keep")]
+    metadata: MetadataPolicy,
+    #[arg(short = 'm', long)]
+    no_monochrome_check: bool,
+    #[command(flatten)]
+    resize: ResizeOpt,
+    /// max size of lossy result of lossy jxl/avif input, in % of input size
+    #[arg(long, default_value = "75.0")]
+    lossy_reencode_limit: f32,
+    #[arg(short = 'q', long, default_value = "1.0")]
+    quality_multiplier: f32,
+    /// scale quality multiplier by image size, details, monochrome MSE and bpp
+    #[arg(long)]
+    adaptive_quality: bool,
+    /// search traces of earlier jpeg compression, recompressed jpegs are matched
+    /// with profile rules by their primary quality
+    #[arg(long)]
+    detect_recompression: bool,
+    /// what to do with images that may be monochrome
+    #[arg(long, value_enum, default_value = "ask")]
+    monochrome_policy: MonochromePolicy,
+    /// MSE cutoff for 'threshold' monochrome policy
+    #[arg(long, default_value = "0.8")]
+    monochrome_threshold: f32,
+    /// review queue for images deferred by 'defer' monochrome policy
+    #[arg(long, default_value = "./convert_review.jsonl")]
+    review_queue: PathBuf,
+    /// convert images from review queue
+    #[arg(long, conflicts_with_all = ["input", "output"])]
+    apply_review: Option<PathBuf>,
+    /// revert conversions from journal written with global '--journal'
+    #[arg(long, conflicts_with_all = ["input", "output", "apply_review"])]
+    undo: Option<PathBuf>,
+}
+
+/// Decision for images with ambiguous monochrome MSE
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MonochromePolicy {
+    /// open image and ask user
+    Ask,
+    /// always keep colors
+    Color,
+    /// always convert to grayscale
+    Gray,
+    /// convert to grayscale if MSE is below monochrome threshold
+    Threshold,
+    /// skip image and write it to review queue
+    Defer,
+}
+
+/// Image deferred by monochrome policy, one json line in review queue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ReviewEntry {
+    input: PathBuf,
+    output: PathBuf,
+    monochrome_mse: f32,
+    /// user decision, asked on `--apply-review` if not set
+    monochrome: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    Png,
+    Jpeg,
+    Webp,
+    Jxl,
+    Avif,
+}
+
+impl Format {
+    /// Format from file content
+    fn from_file_format(filepath: &Path) -> Option<Self> {
+        match ImageFormat::sniff(filepath).ok()?? {
+            ImageFormat::Png | ImageFormat::Apng => Some(Self::Png),
+            ImageFormat::Jpeg => Some(Self::Jpeg),
+            ImageFormat::Webp => Some(Self::Webp),
+            ImageFormat::Jxl => Some(Self::Jxl),
+            ImageFormat::Avif => Some(Self::Avif),
+            _ => None,
+        }
+    }
+    fn as_ext(&self) -> &str {
+        match self {
+            Format::Png => ".png",
+            Format::Jpeg => ".jpg",
+            Format::Webp => ".webp",
+            Format::Jxl => ".jxl",
+            Format::Avif => ".avif",
+        }
+    }
+}
+
+pub fn main(opt: Opt) -> BResult<()> {
+    if let Some(j) = opt.undo {
+        return journal::undo(&j);
+    }
+    let config_dir = dirs::config_dir().unwrap().join("vert");
+    let profile = Profile::load(
+        &opt.profiles_config_json
+            .unwrap_or_else(|| config_dir.join("convert_profiles.json")),
+        if opt.avif { "avif" } else { &opt.profile },
+        &opt.cmds_config_json
+            .unwrap_or_else(|| config_dir.join("cmds_settings.json")),
+    )?;
+    let options = ConvertOptions {
+        profile,
+        manga_mode: opt.manga,
+        rename_original: opt.rename_original,
+        delete_original: opt.delete_original,
+        metadata: opt.metadata,
+        monochrome_check: !opt.no_monochrome_check,
+        resize: opt.resize,
+        lossy_reencode_limit: opt.lossy_reencode_limit,
+        quality_multiplier: opt.quality_multiplier,
+        adaptive_quality: opt.adaptive_quality,
+        detect_recompression: opt.detect_recompression,
+        monochrome_policy: opt.monochrome_policy,
+        monochrome_threshold: opt.monochrome_threshold,
+        review_queue: opt.review_queue,
+    };
+    if let Some(queue) = opt.apply_review {
+        return apply_review(&queue, options);
+    }
+    process_images(
+        opt.input.ok_or("No input path")?,
+        opt.output.ok_or("No output path")?,
+        options,
+    )?;
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct ConvertOptions {
+    pub profile: Profile,
+    pub manga_mode: Option<u8>,
+    pub rename_original: bool,
+    pub delete_original: bool,
+    pub metadata: MetadataPolicy,
+    pub monochrome_check: bool,
+    pub resize: ResizeOpt,
+    pub lossy_reencode_limit: f32,
+    pub quality_multiplier: f32,
+    pub adaptive_quality: bool,
+    pub detect_recompression: bool,
+    pub monochrome_policy: MonochromePolicy,
+    pub monochrome_threshold: f32,
+    pub review_queue: PathBuf,
+}
+
+pub fn process_images(
+    input_path: PathBuf,
+    output_path: PathBuf,
+    options: ConvertOptions,
+) -> BResult<()> {
+    // LOAD
+    let mut format = Format::from_file_format(&input_path).ok_or("Can't parse image format")?;
+    if is_animated::is_animated(&input_path)? {
+        return process_animated_image(input_path, output_path, format, options);
+    }
+    // jxl and avif are decoded to png by external decoders, keeping source bit depth
+    let decoded = match format {
+        Format::Jxl => Some(utils::image_jxl_decode(&input_path)?),
+        Format::Avif => Some(utils::image_avif_decode(&input_path, None)?),
+        _ => None,
+    };
+    let lossy_source = is_lossy_source(&input_path, format)?;
+    let mut filepath = match &decoded {
+        Some(tmp) => tmp.path().to_path_buf(),
+        None => input_path.clone(),
+    };
+    if decoded.is_some() {
+        format = Format::Png;
+    }
+    let (mut img, mut icc) = image_open_with_icc(&filepath).map_err(|e| {
+        format!(
+            "Can't open input image file from input_path {}: {}",
+            &input_path.display(),
+            e
+        )
+    })?;
+
+    // ESTIMATE JPEG QUALITY
+    let mut recompressed = false;
+    let quality = match format {
+        Format::Jpeg => {
+            let data = std::fs::read(&input_path)?;
+            let mut info = JpegInfo::parse(&data)?;
+            if options.detect_recompression {
+                info.detect_recompression(&data)?;
+            }
+            recompressed = info.primary_quality.is_some();
+            // earlier compression with lower quality limits the real quality,
+            // its IJG quality is compared on the scale of the estimate
+            Some(info.primary_quality.map_or(info.estimated_quality, |q| {
+                info.estimated_quality.min(info.estimated_quality_at(q))
+            }))
+        }
+        _ => None,
+    };
+
+    // COLOR PROFILE
+    let mut tmp0 = None;
+    let srgb = match icc.as_deref() {
+        Some(icc) if options.profile.to_srgb() => convert_to_srgb(&img, icc)?,
+        _ => None,
+    };
+    if let Some(srgb) = srgb {
+        tmp0 = Some(tempfile::Builder::new().suffix(".png").tempfile()?);
+        filepath = tmp0.as_ref().unwrap().path().to_path_buf();
+        save_png(&srgb, &filepath, None)?;
+        img = srgb;
+        icc = None;
+        format = Format::Png;
+        println!("converted to sRGB");
+    }
+    // converted pixels must not get source profile back
+    let keep_source_icc = tmp0.is_none();
+
+    // RESIZE
+    let mut tmp1 = None;
+    let size = img.dimensions();
+    if let Some(dims) = options.resize.target_dimensions(size, quality) {
+        tmp1 = Some(tempfile::Builder::new().suffix(".png").tempfile()?);
+        let tmp_path1 = tmp1.as_ref().unwrap().path().to_path_buf();
+        img = options.resize.resize(&img, dims);
+        save_png(&img, &tmp_path1, icc.as_deref())?;
+        format = Format::Png;
+        filepath = tmp_path1;
+        println!("resized to {}x{}", dims.0, dims.1);
+    }
+
+    // PROCESS MANGA
+    if options.manga_mode.is_some() {
+        return process_manga_image(img, filepath, format);
+    }
+
+    // MONOCHROME
+    let monochrome_mse = if options.monochrome_check {
+        image_is_monochrome(&img, false)
+    } else {
+        f32::INFINITY
+    };
+    let Some(is_monochrome) = monochrome_decision(&filepath, monochrome_mse, &options) else {
+        review_queue_push(
+            &options.review_queue,
+            &ReviewEntry {
+                input: std::path::absolute(&input_path)?,
+                output: std::path::absolute(&output_path)?,
+                monochrome_mse,
+                monochrome: None,
+            },
+        )?;
+        println!(
+            "N: {:?}, M_MSE: {:?}, deferred to {}",
+            input_path.display(),
+            monochrome_mse,
+            options.review_queue.display()
+        );
+        return Ok(());
+    };
+    // CONTENT-DEPENDENT QUALITY
+    let mut adaptive_status = String::new();
+    let quality_multiplier = if options.adaptive_quality {
+        let px_count = img.width() * img.height();
+        let edge_ratio = image_edge_ratio(&img);
+        let bpp = (input_path.metadata()?.len() * 8) as f32 / (size.0 * size.1) as f32;
+        adaptive_status = format!(", PX: {}, E: {:.2}, BPP: {:.2}", px_count, edge_ratio, bpp);
+        options.quality_multiplier
+            * content_quality_multiplier(px_count, edge_ratio, monochrome_mse, bpp)
+    } else {
+        options.quality_multiplier
+    };
+
+    let (filepath, img, is_grayscale, tmp2) = image_to_grayscale_if_monochrome(
+        img,
+        filepath,
+        format,
+        icc.as_deref(),
+        monochrome_mse,
+        is_monochrome,
+    )?;
+    if tmp2.is_some() && format != Format::Jpeg {
+        format = Format::Png;
+    }
+
+    // ALPHA
+    let (enc_filepath, alpha, tmp3) = optimise_alpha(img, filepath.clone(), icc.as_deref())?;
+    if tmp3.is_some() {
+        format = Format::Png;
+    }
+
+    println!(
+        "N: {:?}, F: {:?}, M_MSE: {:?}, A: {:?}, Q: {}{}{}, QM: {:.3}",
+        input_path.display(),
+        format,
+        monochrome_mse,
+        alpha,
+        quality.unwrap_or_default(),
+        if recompressed { " (recompressed)" } else { "" },
+        adaptive_status,
+        quality_multiplier,
+    );
+
+    // ENCODE SETTINGS
+    let cmds = options.profile.candidates(
+        &ImageTraits {
+            format,
+            jpeg_quality: quality,
+            monochrome: is_grayscale,
+            alpha: alpha.has_alpha(),
+            animated: false,
+            recompressed,
+        },
+        quality_multiplier,
+    )?;
+
+    // ENCODE
+    // results are compared with image before alpha optimisation,
+    // or with original file if decoded jxl / avif wasn't changed
+    let reference = match &decoded {
+        Some(tmp) if filepath == tmp.path() => &input_path,
+        _ => &filepath,
+    };
+    let lossy_limit = lossy_source.then_some(options.lossy_reencode_limit);
+    let best = encode_and_get_best(&enc_filepath, reference, cmds, lossy_limit)?;
+    let verify_source = if best.1 == "copy" {
+        reference
+    } else {
+        &enc_filepath
+    };
+    save_result(
+        &input_path,
+        &output_path,
+        verify_source,
+        best,
+        keep_source_icc,
+        &options,
+    )?;
+
+    // CLEANUP
+    if let Some(tmp) = decoded {
+        tmp.close()?;
+    }
+    if let Some(tmp) = tmp0 {
+        tmp.close()?;
+    }
+    if let Some(tmp) = tmp1 {
+        tmp.close()?;
+    }
+    if let Some(tmp) = tmp2 {
+        tmp.close()?;
+    }
+    if let Some(tmp) = tmp3 {
+        tmp.close()?;
+    }
+    Ok(())
+}
+
+/// Encode animation with animated rule of the profile, or copy it untouched
+fn process_animated_image(
+    input_path: PathBuf,
+    output_path: PathBuf,
+    format: Format,
+    options: ConvertOptions,
+) -> BResult<()> {
+    println!("N: {:?}, F: {:?}, animated", input_path.display(), format);
+    let traits = ImageTraits {
+        format,
+        jpeg_quality: None,
+        monochrome: false,
+        alpha: false,
+        animated: true,
+        recompressed: false,
+    };
+    let (best, ext) = if options.profile.has_rule(&traits) {
+        let cmds = options
+            .profile
+            .candidates(&traits, options.quality_multiplier)?;
+        let (best, ext, _) = encode_and_get_best(&input_path, &input_path, cmds, None)?;
+        (best, ext)
+    } else {
+        // encoders of default profiles read only apng frames
+        println!("No rule for animated {:?} in profile, copy input", format);
+        (std::fs::read(&input_path)?, "copy".to_string())
+    };
+    // frames can't be compared with decoded default image
+    save_result(
+        &input_path,
+        &output_path,
+        &input_path,
+        (best, ext, false),
+        true,
+        &options,
+    )
+}
+
+/// Write verified result (data, extension, lossless) next to output path
+/// and replace/backup original
+fn save_result(
+    input_path: &Path,
+    output_path: &Path,
+    filepath: &Path,
+    (best, ext, lossless): (Vec<u8>, String, bool),
+    keep_source_icc: bool,
+    options: &ConvertOptions,
+) -> BResult<()> {
+    let copy = ext == "copy";
+    let ext = match ext.as_str() {
+        "copy" => file_ext(filepath)?,
+        _ => ext,
+    };
+
+    // WRITE & VERIFY RESULT
+    let output_path = output_path.with_extension(&ext);
+    let output_dir = match output_path.parent() {
+        Some(p) if p != Path::new("") => p,
+        _ => Path::new("."),
+    };
+    journal::mkdir(output_dir)?;
+    let mut tmp_out = tempfile::Builder::new()
+        .prefix(".ims-")
+        .suffix(&format!(".{}", ext))
+        .tempfile_in(output_dir)?;
+    tmp_out.write_all(&best)?;
+    // metadata is injected first so the persisted bytes are the verified ones
+    let mut source_metadata = Metadata::read(input_path)?;
+    if !keep_source_icc {
+        source_metadata.icc = None;
+    }
+    metadata::apply_policy_from(
+        options.metadata,
+        &source_metadata,
+        input_path,
+        tmp_out.path(),
+    )?;
+    // untouched copy of the source, e.g. animation without rule, isn't decoded again
+    if !(copy && journal::same_content(tmp_out.path(), filepath)?) {
+        verify_output(tmp_out.path(), filepath, lossless)?;
+    }
+    tmp_out
+        .as_file()
+        .set_permissions(input_path.metadata()?.permissions())?;
+    tmp_out.as_file().sync_all()?;
+
+    // BACKUP
+    let output_is_input = std::path::absolute(&output_path)? == std::path::absolute(input_path)?;
+    // replaced files are always kept, original is also kept with '-r'
+    let backup_input = options.rename_original || output_is_input;
+    let backup_output = !output_is_input && output_path.exists();
+    if journal::dry_run() {
+        for (keep, path) in [(backup_input, input_path), (backup_output, &output_path)] {
+            if keep {
+                journal::place(path, &backup_path(path)?, |backup| {
+                    println!("{} --> {}", path.display(), backup.display());
+                    Ok(())
+                })?;
+            }
+        }
+        println!("write {}", output_path.display());
+        if options.delete_original && !output_is_input {
+            println!("rm {}", input_path.display());
+        }
+        return Ok(());
+    }
+    if backup_input {
+        journal::backup(input_path, &backup_path(input_path)?)?;
+    }
+    if backup_output {
+        journal::backup(&output_path, &backup_path(&output_path)?)?;
+    }
+
+    // REPLACE
+    tmp_out.persist(&output_path)?;
+    std::fs::File::open(output_dir)?.sync_all()?;
+    journal::record(journal::Entry::Create {
+        path: output_path.clone(),
+    })?;
+    if output_is_input {
+        return Ok(());
+    }
+    if options.delete_original && journal::recording() {
+        // journaled removal must be restorable by undo
+        trash(input_path)?;
+    } else if options.rename_original || options.delete_original {
+        std::fs::remove_file(input_path)?;
+    }
+    Ok(())
+}
+
+/// '{stem}-bak.{ext}' next to the file
+fn backup_path(path: &Path) -> BResult<PathBuf> {
+    Ok(path.with_file_name(format!(
+        "{}-bak.{}",
+        path.file_stem().unwrap_or_default().to_string_lossy(),
+        file_ext(path)?
+    )))
+}
+
+/// File extension, or extension of content format if path has none
+fn file_ext(path: &Path) -> BResult<String> {
+    match path.extension() {
+        Some(ext) => Ok(ext.to_string_lossy().into_owned()),
+        None => Format::from_file_format(path)
+            .map(|f| f.as_ext().trim_start_matches('.').to_string())
+            .ok_or_else(|| format!("Can't parse image format: {}", path.display()).into()),
+    }
+}
+
+/// Quality multiplier from image content.
+/// Large and flat images tolerate stronger compression,
+/// detailed images and sources with low bpp get less
+fn content_quality_multiplier(
+    px_count: u32,
+    edge_ratio: f32,
+    monochrome_mse: f32,
+    bpp: f32,
+) -> f32 {
+    // 8 MPx is about A4 at 300 dpi
+    let size_factor = (px_count as f32 / 8_000_000.0).powf(0.25).clamp(0.8, 1.25);
+    // centered on 'find detailed' threshold
+    let detail_factor = if edge_ratio.is_finite() {
+        (1.0 - (edge_ratio - 3.25) * 0.05).clamp(0.85, 1.1)
+    } else {
+        1.0
+    };
+    // grayscale or nearly grayscale
+    let monochrome_factor = if monochrome_mse <= 0.8 { 1.05 } else { 1.0 };
+    let bpp_factor = if bpp.is_finite() && bpp > 0.0 {
+        (bpp / 4.0).powf(0.1).clamp(0.9, 1.1)
+    } else {
+        1.0
+    };
+    size_factor * detail_factor * monochrome_factor * bpp_factor
+}
+
+/// Encode image with each candidate, return the best result, its extension and lossless flag.
+/// Results are compared with reference file, which is returned as "copy" if none is better.
+/// Lossy results of lossy source must also be below `lossy_limit` % of reference size
+fn encode_and_get_best(
+    input_path: &Path,
+    reference_path: &Path,
+    cmds: Vec<EncodeCandidate>,
+    lossy_limit: Option<f32>,
+) -> BResult<(Vec<u8>, String, bool)> {
+    let img_filesize = std::fs::metadata(reference_path)?.len() as usize;
+    let mut best = &ImageBuffer::default();
+    let mut best_filesize: usize = img_filesize;
+    let mut best_lossless = true;
+
+    let enc_img_buffers: Vec<ImageBuffer> = cmds
+        .par_iter()
+        .map(|cmd| {
+            let mut buff = cmd.buffer.clone();
+            buff.image_generate(input_path).map(|_| buff)
+        })
+        .collect::<BResult<_>>()?;
+
+    for (i, buff) in enc_img_buffers.iter().enumerate() {
+        let buff_filesize = buff.get_size();
+        let buff_percentage_of_best = (100 * buff_filesize / best_filesize) as i32;
+        let within_lossy_limit = match lossy_limit {
+            Some(limit) if !cmds[i].lossless => {
+                (buff_filesize as f32) < img_filesize as f32 * limit / 100.0
+            }
+            _ => true,
+        };
+        let better = buff_filesize != 0
+            && buff_filesize < img_filesize
+            && buff_percentage_of_best < cmds[i].tolerance
+            && within_lossy_limit;
+
+        let printing_status = format!(
+            "{:>9} --> {:<9}{:4.2}% {is_better}\t{:>6.2}s\t{cmd}",
+            crate::cmds::byte2size(best_filesize as u64),
+            crate::cmds::byte2size(buff_filesize as u64),
+            buff_percentage_of_best,
+            &buff.duration.as_secs_f32(),
+            is_better = if better { "* " } else { "" },
+            cmd = &buff.get_cmd(),
+        );
+        println!("{}", printing_status);
+
+        if better {
+            best = buff;
+            best_filesize = buff_filesize;
+            best_lossless = cmds[i].lossless;
+        }
+    }
+
+    if best_filesize == img_filesize {
+        return Ok((std::fs::read(reference_path)?, "copy".to_string(), true));
+    }
+
+    Ok((
+        best.image.to_owned(),
+        best.extension.to_string(),
+        best_lossless,
+    ))
+}
+
+/// Check that written result decodes to image of encoder input dimensions.
+/// Lossless results of png input must be pixel identical,
+/// lossless jxl from jpeg must reconstruct the same jpeg if it has reconstruction data
+fn verify_output(output: &Path, source: &Path, lossless: bool) -> BResult<()> {
+    let out_img = utils::image_open(output)?;
+    let src_img = utils::image_open(source)?;
+    if out_img.dimensions() != src_img.dimensions() {
+        return Err(format!(
+            "Result dimensions {:?} differ from source {:?}",
+            out_img.dimensions(),
+            src_img.dimensions()
+        )
+        .into());
+    }
+    if !lossless {
+        return Ok(());
+    }
+    match Format::from_file_format(source) {
+        Some(Format::Png) if out_img.to_rgba16() != src_img.to_rgba16() => {
+            Err("Lossless result is not pixel identical to source".into())
+        }
+        Some(Format::Jpeg)
+            if output.extension().unwrap_or_default() == "jxl"
+                && jxl_has_jpeg_reconstruction(&std::fs::read(output)?) =>
+        {
+            if jxl_jpeg_reconstruct(output)? != std::fs::read(source)? {
+                return Err("Reconstructed jpeg differs from source".into());
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Jxl with lossy pixels (xyb or transcoded jpeg), or avif not using identity matrix
+/// which is required for lossless
+fn is_lossy_source(path: &Path, format: Format) -> BResult<bool> {
+    match format {
+        Format::Jxl => Ok(jxl_header::read(&std::fs::read(path)?)?.is_lossy()),
+        Format::Avif => {
+            let data = std::fs::read(path)?;
+            let identity_matrix = isobmff_boxes(&data)
+                .iter()
+                .filter(|b| &b.0 == b"meta")
+                .flat_map(|(_, meta)| isobmff_boxes(meta.get(4..).unwrap_or_default()))
+                .filter(|b| &b.0 == b"iprp")
+                .flat_map(|(_, iprp)| isobmff_boxes(iprp))
+                .filter(|b| &b.0 == b"ipco")
+                .flat_map(|(_, ipco)| isobmff_boxes(ipco))
+                // 'nclx', primaries u16, transfer u16, matrix u16
+                .any(|(typ, colr)| {
+                    &typ == b"colr" && colr.starts_with(b"nclx") && colr.get(8..10) == Some(&[0, 0])
+                });
+            Ok(!identity_matrix)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Search 'jbrd' box in jxl container
+fn jxl_has_jpeg_reconstruction(data: &[u8]) -> bool {
+    isobmff_boxes(data).iter().any(|b| &b.0 == b"jbrd")
+}
+
+fn jxl_jpeg_reconstruct(jxl: &Path) -> BResult<Vec<u8>> {
+    let tf_out = tempfile::Builder::new().suffix(".jpg").tempfile()?;
+    let outp = std::process::Command::new("djxl")
+        .arg(jxl)
+        .arg(tf_out.path())
+        .output()?;
+    utils::command_print_if_error(&outp)?;
+    Ok(std::fs::read(tf_out.path())?)
+}
+
+fn process_manga_image(_img: DynamicImage, _input_path: PathBuf, _format: Format) -> BResult<()> {
+    todo!()
+    // if let Some(ncolors) = opt.manga {
+    //     let temppath = get_tmp_path(&Format::Png);
+    //     img = match format {
+    //         Format::Png => image_pngquant(&filepath, &temppath, ncolors)?,
+    //         _ => {
+    //             img.save(&temppath)?;
+    //             image_pngquant(&temppath, &temppath, ncolors)?
+    //         }
+    //     };
+    //     filepath = temppath;
+    // }
+}
+
+// fn image_pngquant(
+//     filepath: &Path,
+//     save_path: &Path,
+//     ncolors: i8,
+// ) -> BResult<DynamicImage> {
+//     let p = std::process::Command::new("pngquant")
+//         .args(["-o", "-"])
+//         .arg("--nofs")
+//         .arg(ncolors.to_string())
+//         .arg(filepath)
+//         .output()?;
+//     // if p.stdout.len() < std::fs::metadata(filepath)?.len() as usize {
+//     std::fs::write(save_path, &p.stdout)?;
+//     // }
+//     image::load_from_memory_with_format(&p.stdout, image::ImageFormat::Png).map_err(|e| e.into())
+// }
+
+/// Image path, loaded image, monochrome flag and possible handle to temporary file
+type PossibleMonochromeImageBundle = (PathBuf, DynamicImage, bool, Option<NamedTempFile>);
+
+/// Decide if image is monochrome by its MSE.
+/// Ambiguous MSE is resolved by monochrome policy, None if image is deferred to review
+fn monochrome_decision(
+    filepath: &Path,
+    monochrome_mse: f32,
+    options: &ConvertOptions,
+) -> Option<bool> {
+    if monochrome_mse == -1.0 || monochrome_mse == 0.0 {
+        return Some(true);
+    }
+    if monochrome_mse >= 896.0 {
+        return Some(false);
+    }
+    match options.monochrome_policy {
+        MonochromePolicy::Ask => Some(ask_is_monochrome(filepath)),
+        MonochromePolicy::Color => Some(false),
+        MonochromePolicy::Gray => Some(true),
+        MonochromePolicy::Threshold => Some(monochrome_mse <= options.monochrome_threshold),
+        MonochromePolicy::Defer => None,
+    }
+}
+
+fn review_queue_push(queue: &Path, entry: &ReviewEntry) -> BResult<()> {
+    let mut f = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(queue)?;
+    f.write_all((serde_json::to_string(entry)? + "\n").as_bytes())?;
+    Ok(())
+}
+
+/// Convert images from review queue, asking user about images without decision.
+/// Failed entries are kept in the queue
+fn apply_review(queue: &Path, options: ConvertOptions) -> BResult<()> {
+    let reader = std::io::BufReader::new(std::fs::File::open(queue)?);
+    let entries = reader
+        .lines()
+        .filter(|l| l.as_ref().map_or(true, |l| !l.trim().is_empty()))
+        .map(|l| Ok(serde_json::from_str::<ReviewEntry>(&l?)?))
+        .collect::<BResult<Vec<_>>>()?;
+
+    let mut failed = Vec::new();
+    for entry in entries {
+        let is_monochrome = entry
+            .monochrome
+            .unwrap_or_else(|| ask_is_monochrome(&entry.input));
+        let entry_options = ConvertOptions {
+            monochrome_policy: if is_monochrome {
+                MonochromePolicy::Gray
+            } else {
+                MonochromePolicy::Color
+            },
+            ..options.clone()
+        };
+        if let Err(e) = process_images(entry.input.clone(), entry.output.clone(), entry_options) {
+            println!("Can't process image {}: {}", entry.input.display(), e);
+            failed.push(ReviewEntry {
+                monochrome: Some(is_monochrome),
+                ..entry
+            });
+        }
+    }
+
+    std::fs::remove_file(queue)?;
+    for entry in &failed {
+        review_queue_push(queue, entry)?;
+    }
+    Ok(())
+}
+
+/// Convert image to grayscale if image is monochrome.
+/// Return original filepath and image otherwise
+fn image_to_grayscale_if_monochrome(
+    img: DynamicImage,
+    filepath: PathBuf,
+    format: Format,
+    icc: Option<&[u8]>,
+    monochrome_mse: f32,
+    is_monochrome: bool,
+) -> BResult<PossibleMonochromeImageBundle> {
+    if monochrome_mse == -1.0 {
+        return Ok((filepath, img, true, None));
+    }
+    if !is_monochrome {
+        return Ok((filepath, img, false, None));
+    }
+
+    match format {
+        Format::Jpeg => {
+            let tmp = tempfile::Builder::new()
+                .suffix(&format.as_ext())
+                .tempfile()?;
+            let tmp_path = tmp.path().to_path_buf();
+            let _img = jpegtran_grayscale(&filepath, &tmp_path)?;
+            Ok((tmp_path, _img, true, Some(tmp)))
+        }
+        _ => {
+            let tmp = tempfile::Builder::new().suffix(".png").tempfile()?;
+            let tmp_path = tmp.path().to_path_buf();
+            let _img = into_grayscale(img);
+            save_png(&_img, &tmp_path, icc)?;
+            Ok((tmp_path, _img, true, Some(tmp)))
+        }
+    }
+}
+
+fn ask_is_monochrome(filepath: &Path) -> bool {
+    open::that(filepath).unwrap();
+    match tinyfiledialogs::message_box_yes_no(
+        "convert",
+        "Is image monochrome?",
+        tinyfiledialogs::MessageBoxIcon::Question,
+        tinyfiledialogs::YesNo::No,
+    ) {
+        tinyfiledialogs::YesNo::No => false,
+        tinyfiledialogs::YesNo::Yes => true,
+    }
+}
+
+fn jpegtran_grayscale(filepath: &Path, save_path: &Path) -> BResult<DynamicImage> {
+    let p = std::process::Command::new("jpegtran")
+        .arg("-grayscale")
+        .arg("-optimize")
+        .args(["-copy", "all"])
+        .arg(filepath)
+        .output()?;
+    // if p.stdout.len() < std::fs::metadata(filepath)?.len() as usize {
+    std::fs::write(save_path, &p.stdout)?;
+    // }
+    image::load_from_memory_with_format(&p.stdout, image::ImageFormat::Jpeg).map_err(|e| e.into())
+}
@@ -0,0 +1,249 @@
+use std::{collections::HashMap, fs::File, io::Write, path::Path};
+
+use serde::Deserialize;
+
+use crate::{
+    cmds::{settings_load, EncodeSetting, ImageBuffer},
+    BResult,
+};
+
+use super::Format;
+
+/// Named set of rules, the first rule matching the image gives encode candidates
+#[derive(Debug, Clone, Deserialize)]
+pub struct Profile {
+    rules: Vec<Rule>,
+    /// Convert images with non-sRGB icc profile to sRGB before encoding
+    #[serde(default, rename = "to-srgb")]
+    to_srgb: bool,
+    #[serde(skip)]
+    settings: HashMap<String, EncodeSetting>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Rule {
+    format: Option<Format>,
+    /// match jpegs with estimated quality greater than the value
+    jpeg_quality_above: Option<f32>,
+    /// match jpegs with estimated quality less than the value
+    jpeg_quality_below: Option<f32>,
+    monochrome: Option<bool>,
+    alpha: Option<bool>,
+    /// animated images match only rules with `"animated": true`
+    animated: Option<bool>,
+    /// jpeg with traces of earlier compression
+    recompressed: Option<bool>,
+    candidates: Vec<Candidate>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Candidate {
+    /// Setting from cmds config with arguments, e.g. "cjxl_d(%q%)"
+    cmd: String,
+    /// Max size in % of the best result to be accepted
+    tolerance: i32,
+    /// Value for '%q%' in cmd, multiplied by quality multiplier
+    quality: Option<f32>,
+    /// Round quality to integer
+    #[serde(default)]
+    round: bool,
+    /// Encoder output is verified to be pixel identical
+    #[serde(default)]
+    lossless: bool,
+}
+
+/// Encoder command with its tolerance
+#[derive(Debug, Clone)]
+pub(super) struct EncodeCandidate {
+    pub buffer: ImageBuffer,
+    pub tolerance: i32,
+    pub lossless: bool,
+}
+
+/// Properties of the image used to match profile rules
+#[derive(Debug, Clone, Copy)]
+pub(super) struct ImageTraits {
+    pub format: Format,
+    pub jpeg_quality: Option<f32>,
+    pub monochrome: bool,
+    pub alpha: bool,
+    pub animated: bool,
+    pub recompressed: bool,
+}
+
+impl Profile {
+    /// Load profile by name from profiles config, and cmds settings used by its candidates
+    pub fn load(profiles_file: &Path, name: &str, cmds_settings_file: &Path) -> BResult<Self> {
+        let mut profiles = profiles_load(profiles_file)?;
+        let mut profile = profiles
+            .remove(name)
+            .ok_or_else(|| format!("No such profile: {}", name))?;
+        profile.settings = settings_load(cmds_settings_file)?;
+        Ok(profile)
+    }
+
+    pub(super) fn to_srgb(&self) -> bool {
+        self.to_srgb
+    }
+
+    pub(super) fn has_rule(&self, image: &ImageTraits) -> bool {
+        self.rules.iter().any(|r| r.matches(image))
+    }
+
+    /// Encode commands with tolerances from the first rule matching the image
+    pub(super) fn candidates(
+        &self,
+        image: &ImageTraits,
+        quality_multiplier: f32,
+    ) -> BResult<Vec<EncodeCandidate>> {
+        let rule = self
+            .rules
+            .iter()
+            .find(|r| r.matches(image))
+            .ok_or_else(|| format!("No profile rule for image: {:?}", image))?;
+        rule.candidates
+            .iter()
+            .map(|c| {
+                let cmd = match c.quality {
+                    Some(q) if c.round => {
+                        let q = (q * quality_multiplier + 0.5) as i32;
+                        c.cmd.replace("%q%", &q.to_string())
+                    }
+                    Some(q) => {
+                        let q = (q * quality_multiplier * 1000.0).round() / 1000.0;
+                        c.cmd.replace("%q%", &q.to_string())
+                    }
+                    None => c.cmd.to_string(),
+                };
+                Ok(EncodeCandidate {
+                    buffer: ImageBuffer::new_from_setting(&cmd, &self.settings)?,
+                    tolerance: c.tolerance,
+                    lossless: c.lossless,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Rule {
+    fn matches(&self, image: &ImageTraits) -> bool {
+        let quality_matches = match image.jpeg_quality {
+            Some(q) => {
+                self.jpeg_quality_above.is_none_or(|v| q > v)
+                    && self.jpeg_quality_below.is_none_or(|v| q < v)
+            }
+            None => self.jpeg_quality_above.is_none() && self.jpeg_quality_below.is_none(),
+        };
+        self.format.is_none_or(|f| f == image.format)
+            && quality_matches
+            && self.monochrome.is_none_or(|m| m == image.monochrome)
+            && self.alpha.is_none_or(|a| a == image.alpha)
+            && self.animated.unwrap_or(false) == image.animated
+            && self.recompressed.is_none_or(|r| r == image.recompressed)
+    }
+}
+
+fn profiles_load(file: &Path) -> BResult<HashMap<String, Profile>> {
+    if !file.exists() {
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut writer = File::create(file)?;
+        writer.write_all(
+            r#"{
+  "default": {
+    "to-srgb": false,
+    "rules": [
+      {
+        "format": "png",
+        "animated": true,
+        "candidates": [
+          { "cmd": "cjxl_l(9)", "tolerance": 100, "lossless": true }
+        ]
+      },
+      {
+        "format": "png",
+        "candidates": [
+          { "cmd": "cjxl_l(9)", "tolerance": 100, "lossless": true },
+          { "cmd": "cjxl_de(%q%,7)", "quality": 1.0, "tolerance": 45 }
+        ]
+      },
+      {
+        "format": "jpeg",
+        "jpeg-quality-above": 98.0,
+        "candidates": [
+          { "cmd": "cjxl_tr_vardct(7)", "tolerance": 100, "lossless": true },
+          { "cmd": "cjxl_l(9)", "tolerance": 95, "lossless": true },
+          { "cmd": "cjxl_de(%q%,7)", "quality": 1.0, "tolerance": 50 }
+        ]
+      },
+      {
+        "format": "jpeg",
+        "jpeg-quality-below": 90.0,
+        "candidates": [
+          { "cmd": "cjxl_tr_vardct(9)", "tolerance": 100, "lossless": true },
+          { "cmd": "cjxl_de(%q%,7)", "quality": 2.0, "tolerance": 30 }
+        ]
+      },
+      {
+        "format": "jpeg",
+        "candidates": [
+          { "cmd": "cjxl_tr_vardct(7)", "tolerance": 100, "lossless": true },
+          { "cmd": "cjxl_l(9)", "tolerance": 95, "lossless": true },
+          { "cmd": "cjxl_de(%q%,7)", "quality": 1.125, "tolerance": 50 }
+        ]
+      }
+    ]
+  },
+  "avif": {
+    "to-srgb": false,
+    "rules": [
+      {
+        "format": "png",
+        "animated": true,
+        "candidates": [
+          { "cmd": "cjxl_l(9)", "tolerance": 100, "lossless": true }
+        ]
+      },
+      {
+        "format": "png",
+        "candidates": [
+          { "cmd": "cjxl_l(9)", "tolerance": 100, "lossless": true },
+          { "cmd": "avifenc_q(4,%q%)", "quality": 14, "round": true, "tolerance": 35 }
+        ]
+      },
+      {
+        "format": "jpeg",
+        "jpeg-quality-above": 98.0,
+        "candidates": [
+          { "cmd": "cjxl_tr_vardct(7)", "tolerance": 100, "lossless": true },
+          { "cmd": "avifenc_q(4,%q%)", "quality": 14, "round": true, "tolerance": 42 }
+        ]
+      },
+      {
+        "format": "jpeg",
+        "jpeg-quality-below": 90.0,
+        "candidates": [
+          { "cmd": "cjxl_tr_vardct(9)", "tolerance": 100, "lossless": true },
+          { "cmd": "cjxl_de(%q%,7)", "quality": 2.0, "tolerance": 30 }
+        ]
+      },
+      {
+        "format": "jpeg",
+        "candidates": [
+          { "cmd": "cjxl_tr_vardct(7)", "tolerance": 100, "lossless": true },
+          { "cmd": "avifenc_q(4,%q%)", "quality": 21, "round": true, "tolerance": 42 }
+        ]
+      }
+    ]
+  }
+}
+"#
+            .as_bytes(),
+        )?;
+    }
+    let reader = File::open(file)?;
+    let json: HashMap<String, Profile> = serde_json::from_reader(reader)?;
+    Ok(json)
+}
@@ -0,0 +1,152 @@
+use clap::{Args, ValueEnum};
+use image::{imageops::FilterType, ColorType, DynamicImage, Rgba32FImage};
+
+#[derive(Args, Debug, Clone)]
+pub struct ResizeOpt {
+    /// target size in px for 'longest', 'shortest' and 'width' fit, 0 disables resize
+    #[arg(short = 's', long = "resize", default_value = "3508")]
+    pub size: u32,
+    /// how target size is applied to image dimensions
+    #[arg(long, value_enum, default_value = "longest")]
+    pub resize_fit: ResizeFit,
+    /// target pixel count for 'megapixels' fit
+    #[arg(long, default_value = "12.0")]
+    pub resize_megapixels: f32,
+    #[arg(long, value_enum, default_value = "lanczos3")]
+    pub resize_filter: ResizeFilter,
+    /// resize in linear light instead of gamma-encoded values
+    #[arg(long)]
+    pub linear_light: bool,
+    /// don't resize images larger than target by at most this %
+    #[arg(long, default_value = "1.0")]
+    pub resize_slack: f32,
+    /// resize jpegs regardless of estimated quality
+    #[arg(long)]
+    pub resize_any_quality: bool,
+    /// resize only jpegs with estimated quality greater than the value
+    #[arg(long, default_value = "90.0")]
+    pub resize_min_quality: f32,
+}
+
+/// Dimension constrained by target size
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeFit {
+    /// longest side fits target size
+    Longest,
+    /// shortest side fits target size
+    Shortest,
+    /// pixel count fits target megapixels
+    Megapixels,
+    /// width fits target size, for manga pages
+    Width,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl From<ResizeFilter> for FilterType {
+    fn from(f: ResizeFilter) -> Self {
+        match f {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+impl ResizeOpt {
+    /// Downscaled dimensions of the image, None if it already fits the target
+    pub(super) fn target_dimensions(
+        &self,
+        (w, h): (u32, u32),
+        jpeg_quality: Option<f32>,
+    ) -> Option<(u32, u32)> {
+        if self.size == 0 && self.resize_fit != ResizeFit::Megapixels {
+            return None;
+        }
+        if !self.resize_any_quality && jpeg_quality.unwrap_or(100.0) <= self.resize_min_quality {
+            return None;
+        }
+        let size = self.size as f64;
+        let scale = match self.resize_fit {
+            ResizeFit::Longest => size / w.max(h) as f64,
+            ResizeFit::Shortest => size / w.min(h) as f64,
+            ResizeFit::Width => size / w as f64,
+            ResizeFit::Megapixels => {
+                (self.resize_megapixels as f64 * 1_000_000.0 / (w as f64 * h as f64)).sqrt()
+            }
+        };
+        if scale * (1.0 + self.resize_slack as f64 / 100.0) >= 1.0 {
+            return None;
+        }
+        let scaled = |v: u32| ((v as f64 * scale).round() as u32).max(1);
+        Some((scaled(w), scaled(h)))
+    }
+
+    /// Resize image to exact dimensions keeping its color type
+    pub(super) fn resize(&self, img: &DynamicImage, (w, h): (u32, u32)) -> DynamicImage {
+        let filter = self.resize_filter.into();
+        if !self.linear_light {
+            return img.resize_exact(w, h, filter);
+        }
+        let mut linear = img.to_rgba32f();
+        linear.pixels_mut().for_each(|p| {
+            let a = p.0[3];
+            p.0[..3]
+                .iter_mut()
+                .for_each(|c| *c = srgb_to_linear(*c) * a);
+        });
+        let mut resized: Rgba32FImage = image::imageops::resize(&linear, w, h, filter);
+        resized.pixels_mut().for_each(|p| {
+            let a = p.0[3].clamp(0.0, 1.0);
+            p.0[3] = a;
+            p.0[..3].iter_mut().for_each(|c| {
+                *c = if a > 0.0 {
+                    linear_to_srgb((*c / a).clamp(0.0, 1.0))
+                } else {
+                    0.0
+                }
+            });
+        });
+        into_color_type(DynamicImage::ImageRgba32F(resized), img.color())
+    }
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn into_color_type(img: DynamicImage, color: ColorType) -> DynamicImage {
+    match color {
+        ColorType::L8 => img.into_luma8().into(),
+        ColorType::La8 => img.into_luma_alpha8().into(),
+        ColorType::Rgb8 => img.into_rgb8().into(),
+        ColorType::Rgba8 => img.into_rgba8().into(),
+        ColorType::L16 => img.into_luma16().into(),
+        ColorType::La16 => img.into_luma_alpha16().into(),
+        ColorType::Rgb16 => img.into_rgb16().into(),
+        ColorType::Rgba16 => img.into_rgba16().into(),
+        ColorType::Rgb32F => img.into_rgb32f().into(),
+        _ => img,
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::{Args, ValueEnum};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    journal::{self, Entry},
+    walk::WalkOpt,
+    BResult,
+};
+
+/// Image check of a `find` subcommand
+pub trait Filter: Sync {
+    /// Name of the value reported for matched images
+    fn metric_name(&self) -> &'static str;
+    /// Directory for matched images when output directory isn't set
+    fn default_out_dir(&self) -> PathBuf;
+    /// Metric value if image matches, None otherwise
+    fn check(&self, img: &Path) -> BResult<Option<f64>>;
+}
+
+/// Value reported for matched image
+#[derive(Debug, Clone, PartialEq)]
+pub enum Metric {
+    Number(f64),
+    Text(String),
+}
+
+impl From<f64> for Metric {
+    fn from(value: f64) -> Self {
+        Self::Number(value)
+    }
+}
+
+impl From<String> for Metric {
+    fn from(value: String) -> Self {
+        Self::Text(value)
+    }
+}
+
+impl std::fmt::Display for Metric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            // integer metrics are printed without decimals
+            Self::Number(v) if v.fract() == 0.0 => write!(f, "{}", v),
+            Self::Number(v) => write!(f, "{:.3}", v),
+            Self::Text(t) => write!(f, "{}", t),
+        }
+    }
+}
+
+impl From<&Metric> for serde_json::Value {
+    fn from(metric: &Metric) -> Self {
+        match metric {
+            Metric::Number(v) if v.fract() == 0.0 => (*v as i64).into(),
+            Metric::Number(v) => (*v).into(),
+            Metric::Text(t) => t.as_str().into(),
+        }
+    }
+}
+
+/// What to do with matched images
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Move,
+    Copy,
+    Hardlink,
+    Symlink,
+    /// print paths, one per line or NUL separated with '-0'
+    Print,
+    /// print json line with path and metric
+    Json,
+    /// move to freedesktop trash
+    Trash,
+}
+
+// Input and action options shared by `find` subcommands
+#[derive(Args, Debug, Clone)]
+pub struct FindOpt {
+    /// input image paths
+    #[arg(required = false, default_value = "./*", display_order = 0)]
+    pub input: Vec<PathBuf>,
+    #[command(flatten)]
+    pub walk: WalkOpt,
+    /// action for matched images [default: move]
+    #[arg(short, long, value_enum)]
+    pub action: Option<Action>,
+    /// output directory path, default depends on subcommand
+    #[arg(short, long)]
+    pub out_dir: Option<PathBuf>,
+    /// separate printed paths by NUL, for 'xargs -0'
+    #[arg(short = '0', long)]
+    pub null: bool,
+    #[arg(long, default_value = "0")]
+    pub nproc: usize,
+}
+
+impl FindOpt {
+    /// Action set by user or move
+    pub fn action(&self) -> Action {
+        self.action.unwrap_or(Action::Move)
+    }
+
+    /// Input paths, images in current dir if input is not set
+    pub fn images(&self) -> BResult<Vec<PathBuf>> {
+        let images = self.walk.expand(&self.input)?;
+        // global pool can be already initialised, keep it
+        let _ = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.nproc)
+            .build_global();
+        Ok(images)
+    }
+
+    /// Check input images in parallel and apply action to matched
+    pub fn run(&self, filter: &impl Filter) -> BResult<()> {
+        let images = self.images()?;
+        let out_dir = self
+            .out_dir
+            .clone()
+            .unwrap_or_else(|| filter.default_out_dir());
+        images.par_iter().for_each(|img| {
+            let res = filter.check(img).and_then(|m| match m {
+                Some(metric) => {
+                    self.apply(img, &out_dir, Some((filter.metric_name(), metric.into())))
+                }
+                None => Ok(()),
+            });
+            if let Err(e) = res {
+                eprintln!("Error processing image {}: {}", img.display(), e);
+            }
+        });
+        Ok(())
+    }
+
+    /// Apply action to matched image, `dest_dir` is used by move, copy and link actions
+    pub fn apply(
+        &self,
+        img: &Path,
+        dest_dir: &Path,
+        metric: Option<(&str, Metric)>,
+    ) -> BResult<()> {
+        match self.action() {
+            Action::Print => {
+                let sep = if self.null { '\0' } else { '\n' };
+                print!("{}{}", img.display(), sep);
+                return Ok(());
+            }
+            Action::Json => {
+                let mut json = serde_json::Map::new();
+                json.insert("file".into(), img.to_string_lossy().into());
+                if let Some((name, value)) = &metric {
+                    json.insert(name.to_string(), value.into());
+                }
+                println!("{}", serde_json::Value::Object(json));
+                return Ok(());
+            }
+            _ => (),
+        }
+        match metric {
+            Some((name, value)) => println!("File: {}\n {}: {}", img.display(), name, value),
+            None => println!("File: {}", img.display()),
+        }
+        if self.action() == Action::Trash {
+            if journal::dry_run() {
+                println!("trash {}", img.display());
+                return Ok(());
+            }
+            return trash(img);
+        }
+
+        let file_name = img
+            .file_name()
+            .ok_or_else(|| format!("Can't get image filename: {}", img.display()))?;
+        if !journal::dry_run() {
+            journal::mkdir(dest_dir)?;
+        }
+        let action = self.action();
+        journal::place(img, &dest_dir.join(file_name), |dest| {
+            if journal::dry_run() {
+                let name = format!("{:?}", action).to_lowercase();
+                println!("{} {} --> {}", name, img.display(), dest.display());
+                return Ok(());
+            }
+            match action {
+                Action::Move => {
+                    journal::move_file(img, dest)?;
+                    return journal::record(Entry::Move {
+                        from: img.to_path_buf(),
+                        to: dest.to_path_buf(),
+                    });
+                }
+                Action::Copy => {
+                    std::fs::copy(img, dest)?;
+                }
+                Action::Hardlink => std::fs::hard_link(img, dest)?,
+                Action::Symlink => symlink(&std::path::absolute(img)?, dest)?,
+                Action::Print | Action::Json | Action::Trash => unreachable!(),
+            }
+            journal::record(Entry::Create {
+                path: dest.to_path_buf(),
+            })
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+/// Move file to home trash following freedesktop trash specification
+pub fn trash(img: &Path) -> BResult<()> {
+    let path = std::path::absolute(img)?;
+    let trash_dir = dirs::data_dir()
+        .ok_or("Can't find user data dir")?
+        .join("Trash");
+    let (files_dir, info_dir) = (trash_dir.join("files"), trash_dir.join("info"));
+    std::fs::create_dir_all(&files_dir)?;
+    std::fs::create_dir_all(&info_dir)?;
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| format!("Can't get image filename: {}", img.display()))?;
+    let stem = Path::new(file_name).file_stem().unwrap_or(file_name);
+    let ext = Path::new(file_name)
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    // info file is created first to reserve the name
+    for n in 0.. {
+        let name = if n == 0 {
+            file_name.to_string_lossy().into_owned()
+        } else {
+            format!("{}.{}{}", stem.to_string_lossy(), n, ext)
+        };
+        let info_path = info_dir.join(format!("{}.trashinfo", name));
+        let mut info = match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&info_path)
+        {
+            Ok(f) => f,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if files_dir.join(&name).exists() {
+            std::fs::remove_file(&info_path)?;
+            continue;
+        }
+        journal::record(Entry::Create {
+            path: info_path.clone(),
+        })?;
+        let res = info
+            .write_all(
+                format!(
+                    "[Trash Info]\nPath={}\nDeletionDate={}\n",
+                    percent_encode(&path),
+                    deletion_date()
+                )
+                .as_bytes(),
+            )
+            .map_err(|e| e.into())
+            .and_then(|_| journal::move_file(&path, &files_dir.join(&name)));
+        if let Err(e) = res {
+            std::fs::remove_file(&info_path)?;
+            return Err(e);
+        }
+        return journal::record(Entry::Move {
+            from: path,
+            to: files_dir.join(&name),
+        });
+    }
+    unreachable!()
+}
+
+/// Escape path for trash info, keeping unreserved characters and '/'
+fn percent_encode(path: &Path) -> String {
+    path.as_os_str()
+        .as_encoded_bytes()
+        .iter()
+        .map(|&b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Current UTC time as 'YYYY-MM-DDThh:mm:ss'
+fn deletion_date() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // civil from days, http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
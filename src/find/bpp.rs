@@ -1,17 +1,22 @@
 use std::path::{Path, PathBuf};
 
 use clap::Args;
-use rayon::iter::{ParallelBridge, ParallelIterator};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
 use crate::{utils, BResult};
 
+use super::action::{Filter, FindOpt};
+
 #[derive(Args, Debug, Clone)]
 pub struct Opt {
-    /// input image paths
-    #[arg(required = false, default_value = "./*", display_order = 0)]
-    input: Vec<PathBuf>,
+    #[command(flatten)]
+    find: FindOpt,
     /// sort images w/ bpp greater than the target
-    #[arg(short, conflicts_with = "greater")]
+    #[arg(
+        short,
+        conflicts_with = "greater",
+        required_unless_present_any = ["greater", "buckets", "report"]
+    )]
     lesser: Option<f32>,
     /// sort images w/ bpp less than the target
     #[arg(short, conflicts_with = "lesser")]
@@ -19,59 +24,143 @@ pub struct Opt {
     /// Custom metric: bpp + px_count / 2048^2
     #[arg(short = 'm')]
     custom_metric: bool,
-    #[arg(long, default_value = "0")]
-    nproc: usize,
+    /// sort images into dirs by bpp ranges between the values, e.g. '1,2,4,8'
+    #[arg(long, value_delimiter = ',', conflicts_with_all = ["lesser", "greater"])]
+    buckets: Vec<f32>,
+    /// only print bpp histogram and percentiles, with '--buckets' as histogram bins
+    #[arg(long, conflicts_with_all = ["lesser", "greater"])]
+    report: bool,
 }
 
 pub fn main(opt: Opt) -> BResult<()> {
-    let out_dir = std::path::PathBuf::from(unwrap_two(opt.lesser, opt.greater).to_string());
-    let images = utils::ims_init(&opt.input, &out_dir, Some(opt.nproc))?;
-
-    images
-        .iter()
-        .par_bridge()
-        .for_each(|img| process_image(img, &out_dir, &opt).unwrap());
+    if opt.buckets.windows(2).any(|w| w[0] >= w[1]) {
+        return Err("Bucket values must be increasing".into());
+    }
+    if opt.report {
+        report(&opt)
+    } else if !opt.buckets.is_empty() {
+        distribute(&opt)
+    } else {
+        opt.find.run(&opt)
+    }
+}
 
+/// Apply action to every image with a directory named by its bpp range
+fn distribute(opt: &Opt) -> BResult<()> {
+    let images = opt.find.images()?;
+    let out_dir = opt.find.out_dir.clone().unwrap_or_default();
+    images.par_iter().for_each(|img| {
+        let res = opt.metric(img).and_then(|m| {
+            let bucket = bucket_index(&opt.buckets, m);
+            let dest_dir = out_dir.join(bucket_name(&opt.buckets, bucket));
+            opt.find
+                .apply(img, &dest_dir, Some(("bpp", (m as f64).into())))
+        });
+        if let Err(e) = res {
+            eprintln!("Error processing image {}: {}", img.display(), e);
+        }
+    });
     Ok(())
 }
 
-fn unwrap_two<T>(l: Option<T>, b: Option<T>) -> T {
-    match l {
-        Some(val) => val,
-        None => b.expect("Both options is None"),
+/// Print bpp percentiles and histogram of input images
+fn report(opt: &Opt) -> BResult<()> {
+    let images = opt.find.images()?;
+    let mut values: Vec<f32> = images
+        .par_iter()
+        .filter_map(|img| match opt.metric(img) {
+            Ok(m) => Some(m),
+            Err(e) => {
+                eprintln!("Error processing image {}: {}", img.display(), e);
+                None
+            }
+        })
+        .collect();
+    if values.is_empty() {
+        return Err("No images".into());
     }
-}
+    values.sort_unstable_by(f32::total_cmp);
 
-fn process_image(img: &Path, out_dir: &std::path::Path, opt: &Opt) -> BResult<()> {
-    let img_filesize = img.metadata()?.len();
-    let img_dimensions = image::image_dimensions(img)?;
-    let px_count = img_dimensions.0 * img_dimensions.1;
-    let img_bpp = (img_filesize * 8) as f32 / px_count as f32;
-    let img_metric = if opt.custom_metric {
-        img_bpp + px_count as f32 / 4194304_f32
+    println!("count: {}", values.len());
+    println!(
+        "mean: {:.3}",
+        values.iter().sum::<f32>() / values.len() as f32
+    );
+    for p in [0, 10, 25, 50, 75, 90, 100] {
+        // nearest rank
+        let i = (p as f32 / 100.0 * (values.len() - 1) as f32).round() as usize;
+        println!("p{:<4} {:.3}", p, values[i]);
+    }
+
+    let edges = if opt.buckets.is_empty() {
+        vec![0.5, 1.0, 2.0, 4.0, 8.0, 16.0]
     } else {
-        img_bpp
+        opt.buckets.clone()
     };
+    let mut counts = vec![0usize; edges.len() + 1];
+    for v in &values {
+        counts[bucket_index(&edges, *v)] += 1;
+    }
+    let max = *counts.iter().max().unwrap();
+    for (i, count) in counts.iter().enumerate() {
+        let bar = "#".repeat((count * 40).div_ceil(max));
+        println!("{:>12} {:>6} {}", bucket_name(&edges, i), count, bar);
+    }
+    Ok(())
+}
 
-    println!("File: {}\n bpp: {:.3}", img.display(), img_metric);
-    let mut save_flag: bool = false;
-    match opt.lesser {
-        Some(val) => {
-            if img_metric < val {
-                save_flag = true;
-            }
-        }
-        None => {
-            let val = opt.greater.unwrap();
-            if img_metric > val {
-                save_flag = true;
-            }
-        }
+/// Index of bpp range containing value
+fn bucket_index(edges: &[f32], value: f32) -> usize {
+    edges.iter().position(|&e| value < e).unwrap_or(edges.len())
+}
+
+/// Name of bpp range, e.g. 'bpp-1', 'bpp1-2', 'bpp8-'
+fn bucket_name(edges: &[f32], i: usize) -> String {
+    match i {
+        0 => format!("bpp-{}", edges[0]),
+        i if i == edges.len() => format!("bpp{}-", edges[i - 1]),
+        i => format!("bpp{}-{}", edges[i - 1], edges[i]),
+    }
+}
+
+impl Filter for Opt {
+    fn metric_name(&self) -> &'static str {
+        "bpp"
     }
-    if save_flag {
-        let save_path = out_dir.join(img.file_name().unwrap());
-        std::fs::rename(img, save_path)?;
+
+    fn default_out_dir(&self) -> PathBuf {
+        PathBuf::from(unwrap_two(self.lesser, self.greater).to_string())
     }
 
-    Ok(())
+    fn check(&self, img: &Path) -> BResult<Option<f64>> {
+        let img_metric = self.metric(img)?;
+        let matched = match self.lesser {
+            Some(val) => img_metric < val,
+            None => img_metric > self.greater.unwrap(),
+        };
+        Ok(matched.then_some(img_metric as f64))
+    }
+}
+
+impl Opt {
+    /// Bits per pixel, or custom metric with '-m'
+    fn metric(&self, img: &Path) -> BResult<f32> {
+        let img_filesize = img.metadata()?.len();
+        let img_dimensions = utils::image_dimensions(img)?;
+        let px_count = img_dimensions.0 * img_dimensions.1;
+        let img_bpp = (img_filesize * 8) as f32 / px_count as f32;
+        let img_metric = if self.custom_metric {
+            img_bpp + px_count as f32 / 4194304_f32
+        } else {
+            img_bpp
+        };
+        Ok(img_metric)
+    }
+}
+
+fn unwrap_two<T>(l: Option<T>, b: Option<T>) -> T {
+    match l {
+        Some(val) => val,
+        None => b.expect("Both options is None"),
+    }
 }
@@ -0,0 +1,150 @@
+use std::{
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use clap::Args;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use zune_jpeg::{zune_core::options::DecoderOptions, JpegDecoder};
+
+use crate::{jpegquality::JpegInfo, png_chunks::PngChunks, sniff::ImageFormat, utils, BResult};
+
+use super::action::FindOpt;
+
+#[derive(Args, Debug, Clone)]
+pub struct Opt {
+    #[command(flatten)]
+    find: FindOpt,
+}
+
+pub fn main(opt: Opt) -> BResult<()> {
+    let images = opt.find.images()?;
+    let out_dir = opt
+        .find
+        .out_dir
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("./corrupt"));
+    images.par_iter().for_each(|img| {
+        let res = corruption(img).and_then(|reason| match reason {
+            Some(reason) => opt
+                .find
+                .apply(img, &out_dir, Some(("reason", reason.into()))),
+            None => Ok(()),
+        });
+        if let Err(e) = res {
+            eprintln!("Error processing image {}: {}", img.display(), e);
+        }
+    });
+    Ok(())
+}
+
+/// Validate file structure and decode image, reason if image is broken
+pub fn corruption(path: &Path) -> BResult<Option<String>> {
+    let Some(format) = ImageFormat::sniff(path)? else {
+        return Ok(Some("unknown format".into()));
+    };
+    let reason = match format {
+        ImageFormat::Png | ImageFormat::Apng => png_corruption(path)?,
+        ImageFormat::Jpeg => jpeg_corruption(path)?,
+        ImageFormat::Webp => webp_corruption(path)?,
+        _ => None,
+    };
+    if reason.is_some() {
+        return Ok(reason);
+    }
+    match format {
+        ImageFormat::Jxl => external_decode(Command::new("djxl"), path),
+        ImageFormat::Avif => {
+            let mut cmd = Command::new("avifdec");
+            cmd.args(["-d", "8", "--png-compress", "0"]);
+            external_decode(cmd, path)
+        }
+        // no decoder
+        ImageFormat::Heif => Ok(None),
+        _ => Ok(utils::image_open(path)
+            .err()
+            .map(|e| format!("decode error: {}", e))),
+    }
+}
+
+/// Chunk crc and `IEND`
+fn png_corruption(path: &Path) -> BResult<Option<String>> {
+    let png = PngChunks::read(path)?;
+    if let Some(c) = png.chunks.iter().find(|c| !c.crc_ok) {
+        return Ok(Some(format!(
+            "crc mismatch in {} at {}",
+            c.type_str(),
+            c.offset
+        )));
+    }
+    Ok(if png.truncated {
+        Some("truncated chunk".into())
+    } else if !png.iend {
+        Some("missing IEND".into())
+    } else {
+        None
+    })
+}
+
+/// Marker scan reaches `EOI`, and entropy-coded data decodes without errors that
+/// the lenient decoder of `image` conceals. Data after `EOI` is only noted
+fn jpeg_corruption(path: &Path) -> BResult<Option<String>> {
+    let data = std::fs::read(path)?;
+    let info = match JpegInfo::parse(&data) {
+        Ok(info) => info,
+        Err(e) => return Ok(Some(format!("header error: {}", e))),
+    };
+    if !info.eoi {
+        return Ok(Some("missing EOI".into()));
+    }
+    if info.trailer > 0 {
+        eprintln!(
+            "Note: {} bytes after EOI in {}",
+            info.trailer,
+            path.display()
+        );
+    }
+    let options = DecoderOptions::default()
+        .set_strict_mode(true)
+        .set_max_width(usize::MAX)
+        .set_max_height(usize::MAX);
+    let mut decoder = JpegDecoder::new_with_options(data.as_slice(), options);
+    Ok(decoder
+        .decode()
+        .err()
+        .map(|e| format!("decode error: {}", e)))
+}
+
+/// RIFF chunk size matches file size
+fn webp_corruption(path: &Path) -> BResult<Option<String>> {
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut size = [0u8; 4];
+    file.seek(SeekFrom::Start(4))?;
+    file.read_exact(&mut size)?;
+    let riff_len = u32::from_le_bytes(size) as u64 + 8;
+    Ok(match riff_len.cmp(&len) {
+        std::cmp::Ordering::Greater => Some(format!("truncated, RIFF size {} > {}", riff_len, len)),
+        std::cmp::Ordering::Less => {
+            Some(format!("data after RIFF, RIFF size {} < {}", riff_len, len))
+        }
+        std::cmp::Ordering::Equal => None,
+    })
+}
+
+/// Decode to temporary png, missing decoder is an error, not corruption
+fn external_decode(mut cmd: Command, path: &Path) -> BResult<Option<String>> {
+    let tmp = tempfile::Builder::new().suffix(".png").tempfile()?;
+    let output = cmd.arg(path).arg(tmp.path()).output()?;
+    if output.status.success() {
+        return Ok(None);
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let msg = stderr.lines().rfind(|l| !l.trim().is_empty()).unwrap_or("");
+    Ok(Some(format!(
+        "{} failed: {}",
+        cmd.get_program().to_string_lossy(),
+        msg.trim()
+    )))
+}
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use clap::Args;
 use image::{
@@ -8,36 +8,58 @@ use image::{
     Luma,
 };
 
-use crate::BResult;
+use crate::{utils, BResult};
 // use imageproc::definitions::Image;
 
+use super::action::{Action, Filter, FindOpt};
+
 #[derive(Args, Debug, Clone)]
 pub struct Opt {
-    /// input file
-    #[arg(short)]
-    input: PathBuf,
-    /// mv dir
-    #[arg(short)]
-    output: Option<PathBuf>,
+    #[command(flatten)]
+    find: FindOpt,
     /// threshold to mv
     #[arg(short, default_value = "3.25")]
     threshold: f32,
+    // deprecated, same as positional input
+    #[arg(short = 'i', hide = true)]
+    input_file: Option<PathBuf>,
+}
+
+pub fn main(mut opt: Opt) -> BResult<()> {
+    if let Some(input) = opt.input_file.take() {
+        opt.find.input = vec![input];
+    }
+    // images are only printed unless output directory or action is set
+    if opt.find.action.is_none() && opt.find.out_dir.is_none() {
+        opt.find.action = Some(Action::Print);
+    }
+    opt.find.run(&opt)
 }
 
-pub fn main(opt: Opt) -> BResult<()> {
-    let img = image::open(&opt.input)?;
-    if image_is_detailed(&img, opt.threshold) {
-        if let (Some(o), Some(n)) = (opt.output, &opt.input.file_name()) {
-            if !o.exists() {
-                std::fs::create_dir(&o)?;
-            }
-            std::fs::rename(&opt.input, o.join(n))?;
-        }
+impl Filter for Opt {
+    fn metric_name(&self) -> &'static str {
+        "edge_ratio"
+    }
+
+    fn default_out_dir(&self) -> PathBuf {
+        PathBuf::from("./detailed")
+    }
+
+    fn check(&self, img: &Path) -> BResult<Option<f64>> {
+        let ratio = image_edge_ratio(&utils::image_open(img)?);
+        Ok((ratio > self.threshold).then_some(ratio as f64))
     }
-    Ok(())
 }
 
 pub fn image_is_detailed(img: &DynamicImage, threshold: f32) -> bool {
+    let sum_div = image_edge_ratio(img);
+    println!("sum_div: {}", sum_div);
+
+    sum_div > threshold
+}
+
+/// Ratio of edges found by canny filter with low thresholds to edges with high thresholds
+pub fn image_edge_ratio(img: &DynamicImage) -> f32 {
     let mut img = img.to_luma8();
     imageproc::contrast::equalize_histogram_mut(&mut img);
     // let wh = img.dimensions();
@@ -79,10 +101,7 @@ pub fn image_is_detailed(img: &DynamicImage, threshold: f32) -> bool {
     //     );
     let out = imageproc::edges::canny(&img, 128.0, 256.0);
     let out1 = imageproc::edges::canny(&img, 32.0, 64.0);
-    let sum_div = pixels_sum(&out1) as f32 / pixels_sum(&out) as f32;
-    println!("sum_div: {}", sum_div);
-
-    sum_div > threshold
+    pixels_sum(&out1) as f32 / pixels_sum(&out) as f32
 }
 
 fn pixels_sum(img: &ImageBuffer<Luma<u8>, Vec<u8>>) -> i64 {
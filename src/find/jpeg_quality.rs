@@ -0,0 +1,50 @@
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+
+use crate::{jpegquality::jpeg_quality, sniff::ImageFormat, BResult};
+
+use super::action::{Filter, FindOpt};
+
+#[rustfmt::skip]
+#[derive(Args, Debug, Clone)]
+pub struct Opt {
+    #[command(flatten)]
+    find: FindOpt,
+    /// sort jpegs w/ quality less than the target
+    #[arg(short, required_unless_present = "greater")]
+    lesser: Option<f32>,
+    /// sort jpegs w/ quality greater than the target, with '-l' quality within the range
+    #[arg(short, required_unless_present = "lesser")]
+    greater: Option<f32>,
+}
+
+pub fn main(opt: Opt) -> BResult<()> {
+    opt.find.run(&opt)
+}
+
+impl Filter for Opt {
+    fn metric_name(&self) -> &'static str {
+        "quality"
+    }
+
+    /// Named by the range, e.g. './q75-90'
+    fn default_out_dir(&self) -> PathBuf {
+        PathBuf::from(match (self.greater, self.lesser) {
+            (Some(g), Some(l)) => format!("q{}-{}", g, l),
+            (Some(g), None) => format!("q{}-", g),
+            (None, Some(l)) => format!("q-{}", l),
+            (None, None) => unreachable!(),
+        })
+    }
+
+    fn check(&self, img: &Path) -> BResult<Option<f64>> {
+        if ImageFormat::sniff(img)? != Some(ImageFormat::Jpeg) {
+            return Ok(None);
+        }
+        let quality = jpeg_quality(img)?;
+        let in_range =
+            self.lesser.is_none_or(|l| quality < l) && self.greater.is_none_or(|g| quality > g);
+        Ok(in_range.then_some(quality as f64))
+    }
+}
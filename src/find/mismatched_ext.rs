@@ -0,0 +1,53 @@
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{journal, sniff::ImageFormat, BResult};
+
+use super::action::FindOpt;
+
+#[derive(Args, Debug, Clone)]
+pub struct Opt {
+    #[command(flatten)]
+    find: FindOpt,
+    /// rename files to the extension of their content instead of action
+    #[arg(long)]
+    fix: bool,
+}
+
+pub fn main(opt: Opt) -> BResult<()> {
+    let images = opt.find.images()?;
+    let out_dir = opt
+        .find
+        .out_dir
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("./mismatched-ext"));
+    images.par_iter().for_each(|img| {
+        if let Err(e) = process(&opt, img, &out_dir) {
+            eprintln!("Error processing image {}: {}", img.display(), e);
+        }
+    });
+    Ok(())
+}
+
+fn process(opt: &Opt, img: &Path, out_dir: &Path) -> BResult<()> {
+    let Some(format) = ImageFormat::sniff(img)? else {
+        return Ok(());
+    };
+    if format.matches_extension(img) {
+        return Ok(());
+    }
+    if !opt.fix {
+        let content = format!("{:?}", format).to_lowercase();
+        return opt
+            .find
+            .apply(img, out_dir, Some(("content", content.into())));
+    }
+    let fixed = img.with_extension(format.extensions()[0]);
+    // dry run prints the move itself
+    if let (Some(dest), false) = (journal::rename(img, &fixed)?, journal::dry_run()) {
+        println!("{} --> {}", img.display(), dest.display());
+    }
+    Ok(())
+}
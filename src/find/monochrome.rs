@@ -3,55 +3,46 @@ use std::path::{Path, PathBuf};
 
 use clap::Args;
 use image::{GenericImageView, Rgb};
-use rayon::iter::{ParallelBridge, ParallelIterator};
 
 use crate::{utils, BResult};
 
-#[rustfmt::skip]
+use super::action::{Action, Filter, FindOpt};
+
 #[derive(Args, Debug, Clone)]
 pub struct Opt {
-    /// input image paths
-    #[arg(required = false, default_value = "./*", display_order = 0)]
-    input: Vec<PathBuf>,
-    /// output directory path
-    #[arg(short, required = false, default_value = "./monochrome", display_order = 0)]
-    out_dir: PathBuf,
+    #[command(flatten)]
+    find: FindOpt,
     /// MSE cutoff
     #[arg(short, default_value = "0.8")]
     threshold: f32,
-    #[arg(long, default_value = "0")]
-    nproc: usize,
     #[arg(short)]
     grayscale: bool,
-    /// Don't move images
-    #[arg(short = 's')]
+    // deprecated, same as '-a print'
+    #[arg(short = 's', hide = true)]
     test: bool,
 }
 
-pub fn main(opt: Opt) -> BResult<()> {
-    let images = utils::ims_init(&opt.input, opt.out_dir.as_path(), Some(opt.nproc))?;
-
-    images.iter().par_bridge().for_each(|img| {
-        process_image(img, opt.out_dir.as_path(), &opt)
-            .unwrap_or_else(|_| panic!("Error processing image: {}", &img.display()))
-    });
-
-    Ok(())
+pub fn main(mut opt: Opt) -> BResult<()> {
+    if opt.test {
+        opt.find.action = Some(Action::Print);
+    }
+    opt.find.run(&opt)
 }
 
-fn process_image(img: &Path, out_dir: &std::path::Path, opt: &Opt) -> BResult<()> {
-    // println!("File: {}", img.display());
-    let img_image = image::open(img)?;
+impl Filter for Opt {
+    fn metric_name(&self) -> &'static str {
+        "mse"
+    }
 
-    if image_is_monochrome(&img_image, opt.grayscale) <= opt.threshold {
-        if opt.test {
-            return Ok(());
-        }
-        let save_path = out_dir.join(img.file_name().unwrap());
-        std::fs::rename(img, save_path)?;
+    fn default_out_dir(&self) -> PathBuf {
+        PathBuf::from("./monochrome")
     }
 
-    Ok(())
+    fn check(&self, img: &Path) -> BResult<Option<f64>> {
+        let img_image = utils::image_open(img)?;
+        let mse = image_is_monochrome(&img_image, self.grayscale);
+        Ok((mse <= self.threshold).then_some(mse as f64))
+    }
 }
 
 pub fn image_is_monochrome(img: &image::DynamicImage, grayscale: bool) -> f32 {
@@ -124,11 +115,7 @@ fn image_mean_color(image: &image::ImageBuffer<image::Rgb<u8>, Vec<u8>>) -> imag
     }
 
     for v in mean.iter_mut() {
-        if count != 0 {
-            *v /= count;
-        } else {
-            *v = 0;
-        }
+        *v = v.checked_div(count).unwrap_or(0);
     }
 
     image::Rgb::from([mean[0] as u8, mean[1] as u8, mean[2] as u8])
@@ -1,73 +1,48 @@
-use std::{
-    ffi::OsStr,
-    path::{Path, PathBuf},
-};
+use std::path::{Path, PathBuf};
 
 use clap::Args;
-use rayon::iter::{ParallelBridge, ParallelIterator};
 
-use crate::{utils, BResult};
+use crate::{journal, utils, BResult};
+
+use super::action::{Filter, FindOpt};
 
 #[rustfmt::skip]
 #[derive(Args, Debug, Clone)]
 pub struct Opt {
-    /// input image paths
-    #[arg(required = false, default_value = "./*", display_order = 0)]
-    input: Vec<PathBuf>,
+    #[command(flatten)]
+    find: FindOpt,
     /// search target
     #[arg(short = 's', long = "size", required = false, default_value = "3508", display_order = 0)]
     px_size: u32,
-    /// keep empty folder after sorting
-    #[arg(long)]
+    // deprecated, create output directory even if nothing matches
+    #[arg(long, hide = true)]
     keep_empty: bool,
-    #[arg(long, default_value = "0")]
-    nproc: usize,
 }
 
 pub fn main(opt: Opt) -> BResult<()> {
-    let out_dir = Path::new("./").join(opt.px_size.to_string());
-    let images = utils::ims_init(&opt.input, &out_dir, Some(opt.nproc))?;
-    let images_to_mv = get_images_to_mv(&images, &opt);
-
-    if images_to_mv.is_empty() {
-        return Ok(());
+    if opt.keep_empty && !journal::dry_run() {
+        journal::mkdir(
+            &opt.find
+                .out_dir
+                .clone()
+                .unwrap_or_else(|| opt.default_out_dir()),
+        )?;
     }
+    opt.find.run(&opt)
+}
 
-    for image in images_to_mv {
-        let filename = image
-            .file_name()
-            .and_then(OsStr::to_str)
-            .ok_or_else(|| format!("Can't get image filename: {}", &image.display()))?;
-        std::fs::rename(&image, out_dir.join(filename))?;
+impl Filter for Opt {
+    fn metric_name(&self) -> &'static str {
+        "size"
     }
 
-    Ok(())
-}
-
-fn get_images_to_mv(images: &[PathBuf], opt: &Opt) -> Vec<PathBuf> {
-    images
-        .iter()
-        .par_bridge()
-        .filter_map(|image| {
-            if is_image_to_move(image, opt).unwrap_or_else(|_| {
-                panic!(
-                    "Error, cannot check image dimmensions\n{}",
-                    &image.display()
-                )
-            }) {
-                Some(image.to_owned())
-            } else {
-                None
-            }
-        })
-        .collect()
-}
+    fn default_out_dir(&self) -> PathBuf {
+        Path::new("./").join(self.px_size.to_string())
+    }
 
-fn is_image_to_move(image: &Path, opt: &Opt) -> image::ImageResult<bool> {
-    let img_dimmensions = image::image_dimensions(image)?;
-    if img_dimmensions.0 > opt.px_size || img_dimmensions.1 > opt.px_size {
-        Ok(true)
-    } else {
-        Ok(false)
+    fn check(&self, image: &Path) -> BResult<Option<f64>> {
+        let img_dimmensions = utils::image_dimensions(image)?;
+        let longest = img_dimmensions.0.max(img_dimmensions.1);
+        Ok((longest > self.px_size).then_some(longest as f64))
     }
 }
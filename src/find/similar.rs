@@ -11,22 +11,20 @@ use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use zip::write::FileOptions;
 
-use crate::{
-    utils::{self, mkdir},
-    BResult,
-};
+use crate::{utils, BResult};
+
+use super::action::{Action, FindOpt};
 
 #[rustfmt::skip]
 #[derive(Args, Debug, Clone)]
 pub struct Opt {
-    /// input image paths
-    #[arg(required = false, default_value = "./*", display_order = 0)]
-    input: Vec<PathBuf>,
+    #[command(flatten)]
+    find: FindOpt,
     /// save image hashes to zipped json file
     #[arg(short)]
     storage: Option<PathBuf>,
-    /// no_move
-    #[arg(short)]
+    // deprecated, same as '-a print'
+    #[arg(short = 'n', hide = true)]
     no_move: bool,
 }
 
@@ -35,12 +33,11 @@ struct JsonData {
     map: HashMap<String, String>,
 }
 
-pub fn main(opt: Opt) -> BResult<()> {
-    let mut images = opt.input.to_owned();
-    if images[0].to_string_lossy() == "./*" {
-        images = utils::read_cwd()?;
-        utils::filter_images(&mut images);
+pub fn main(mut opt: Opt) -> BResult<()> {
+    if opt.no_move {
+        opt.find.action = Some(Action::Print);
     }
+    let images = opt.find.images()?;
 
     // Load saved hashes from zipped json
     let mut map = Arc::new(RwLock::new(HashMap::<String, String>::new()));
@@ -55,12 +52,12 @@ pub fn main(opt: Opt) -> BResult<()> {
     // Process images
     let res: HashMap<PathBuf, ImageHash> = images
         .par_iter()
-        .map(|img| {
-            (
-                img.to_path_buf(),
-                gen_hash(img, Arc::clone(&map), &hasher)
-                    .unwrap_or_else(|_| panic!("Error processing image: {}", &img.display())),
-            )
+        .filter_map(|img| match gen_hash(img, Arc::clone(&map), &hasher) {
+            Ok(hash) => Some((img.to_path_buf(), hash)),
+            Err(e) => {
+                eprintln!("Error processing image {}: {}", img.display(), e);
+                None
+            }
         })
         .collect();
     println!("HashMap computed");
@@ -74,16 +71,17 @@ pub fn main(opt: Opt) -> BResult<()> {
     // TODO result groups thumbnails | Imagemagick montage?
     while &inp != "n" {
         inp.clear();
-        std::io::stdin().read_line(&mut inp)?;
+        // closed stdin, e.g. read by '--stdin', is the same as 'n'
+        if std::io::stdin().read_line(&mut inp)? == 0 {
+            break;
+        }
         inp.pop();
         if let Ok(d) = inp.parse() {
             similar = group_similar(&res, d);
         };
     }
 
-    if !opt.no_move {
-        move_simmilar(similar)?;
-    }
+    apply_similar(similar, &opt.find)?;
     Ok(())
 }
 
@@ -122,16 +120,18 @@ fn save_map(storage: &Option<PathBuf>, map: Arc<RwLock<HashMap<String, String>>>
     Ok(())
 }
 
-fn move_simmilar(similar: Vec<Vec<PathBuf>>) -> std::io::Result<()> {
-    for group in similar {
+/// Apply action to groups of similar images, each group has its own directory
+fn apply_similar(similar: Vec<Vec<PathBuf>>, find: &FindOpt) -> BResult<()> {
+    for (i, group) in similar.iter().enumerate() {
         if group.len() == 1 {
             continue;
         }
-        let group_dir = &group[0].with_extension("");
-        mkdir(group_dir).unwrap();
+        let group_dir = match &find.out_dir {
+            Some(dir) => dir.join(group[0].file_stem().unwrap()),
+            None => group[0].with_extension(""),
+        };
         for image in group {
-            println!("{}", &image.display());
-            std::fs::rename(&image, group_dir.join(image.file_name().unwrap()))?;
+            find.apply(image, &group_dir, Some(("group", (i as f64).into())))?;
         }
     }
     Ok(())
@@ -180,11 +180,7 @@ fn gen_hash(
     if let Some(h) = found {
         hash = ImageHash::from_base64(&h).unwrap();
     } else {
-        let img = match img.extension().unwrap_or_default() {
-            x if x == "jxl" => image_jxl_decode(img).map(|t| image::open(t.path())),
-            x if x == "avif" => image_avif_decode(img).map(|t| image::open(t.path())),
-            _ => Ok(image::open(img)),
-        }??;
+        let img = utils::image_open(img)?;
         let h = hasher.hash_image(&img);
         map.write()
             .unwrap()
@@ -194,37 +190,3 @@ fn gen_hash(
 
     Ok(hash)
 }
-
-fn image_jxl_decode(i: &Path) -> BResult<tempfile::NamedTempFile> {
-    let tf_out = tempfile::Builder::new().suffix(".png").tempfile()?;
-    let outp = std::process::Command::new("djxl")
-        .arg(i)
-        .arg(tf_out.path())
-        .output()?;
-    command_print_if_error(&outp)?;
-    Ok(tf_out)
-}
-
-fn image_avif_decode(i: &Path) -> BResult<tempfile::NamedTempFile> {
-    let tf_out = tempfile::Builder::new().suffix(".png").tempfile()?;
-    let outp = std::process::Command::new("avifdec")
-        .args(["-d", "8", "--png-compress", "0"])
-        .arg(i)
-        .arg(tf_out.path())
-        .output()?;
-    command_print_if_error(&outp)?;
-    Ok(tf_out)
-}
-
-fn command_print_if_error(output: &std::process::Output) -> std::io::Result<()> {
-    if output.status.success() {
-        Ok(())
-    } else {
-        let o = [&output.stderr, "\n".as_bytes(), &output.stdout].concat();
-        println!("{}", std::str::from_utf8(&o).unwrap());
-        Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "Command returned error status",
-        ))
-    }
-}
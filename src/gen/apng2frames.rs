@@ -0,0 +1,220 @@
+use std::{
+    io::{BufReader, Cursor, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::Args;
+use image::{DynamicImage, ImageBuffer, ImageFormat, Rgba};
+use png::{BitDepth, BlendOp, ColorType, DisposeOp, Transformations};
+use serde::Serialize;
+use zip::write::FileOptions;
+
+use crate::BResult;
+
+type Canvas = ImageBuffer<Rgba<u16>, Vec<u16>>;
+
+#[derive(Args, Debug, Clone)]
+pub struct Opt {
+    /// input apng
+    #[arg(display_order = 0)]
+    input: PathBuf,
+    /// output folder, or archive with '--zip'. Default is named by the input
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// pack frames and 'animation.json' into zip archive for 'zip2video'
+    #[arg(short, long)]
+    zip: bool,
+    /// force overwrite existing output
+    #[arg(short = 'y')]
+    overwrite: bool,
+}
+
+#[derive(Serialize)]
+struct Animation {
+    frames: Vec<Frame>,
+}
+
+#[derive(Serialize)]
+struct Frame {
+    file: String,
+    /// delay in milliseconds
+    delay: u64,
+}
+
+pub fn main(opt: Opt) -> BResult<()> {
+    let output = opt.output.clone().unwrap_or_else(|| {
+        if opt.zip {
+            opt.input.with_extension("zip")
+        } else {
+            opt.input.with_extension("")
+        }
+    });
+    if output.exists() && !opt.overwrite {
+        return Err(format!("Output already exists: {}", output.display()).into());
+    }
+
+    let (frames, animation) = decode_frames(&opt.input)?;
+    if opt.zip {
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&output)?);
+        // frames are already compressed
+        let stored = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (frame, data) in animation.frames.iter().zip(&frames) {
+            zip.start_file(&frame.file, stored)?;
+            zip.write_all(data)?;
+        }
+        zip.start_file("animation.json", FileOptions::default())?;
+        serde_json::to_writer(&mut zip, &animation)?;
+        zip.finish()?;
+    } else {
+        std::fs::create_dir_all(&output)?;
+        for (frame, data) in animation.frames.iter().zip(&frames) {
+            std::fs::write(output.join(&frame.file), data)?;
+        }
+        let json = std::fs::File::create(output.join("animation.json"))?;
+        serde_json::to_writer(json, &animation)?;
+    }
+    println!("{} frames: {}", frames.len(), output.display());
+    Ok(())
+}
+
+/// Decode apng into full composited frames encoded as png
+fn decode_frames(path: &Path) -> BResult<(Vec<Vec<u8>>, Animation)> {
+    let mut decoder = png::Decoder::new(BufReader::new(std::fs::File::open(path)?));
+    decoder.set_transformations(Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+    let info = reader.info();
+    let num_frames = info
+        .animation_control()
+        .ok_or("Not an animated png")?
+        .num_frames;
+    let (width, height) = (info.width, info.height);
+    // IDAT without fcTL is not a part of the animation
+    let skip_default_image = info.frame_control().is_none();
+    let high_bit_depth = reader.output_color_type().1 == BitDepth::Sixteen;
+
+    let mut buf = vec![0; reader.output_buffer_size()];
+    if skip_default_image {
+        reader.next_frame(&mut buf)?;
+    }
+    let mut canvas = Canvas::new(width, height);
+    let mut frames = Vec::new();
+    let mut animation = Animation { frames: Vec::new() };
+    // timestamps are rounded to keep total duration
+    let mut elapsed = 0.0;
+    for i in 0..num_frames {
+        let output_info = reader.next_frame(&mut buf)?;
+        let fc = *reader
+            .info()
+            .frame_control()
+            .ok_or("Missing frame control chunk")?;
+        let subframe = subframe_to_rgba16(&buf[..output_info.buffer_size()], &output_info)?;
+        if fc.x_offset + fc.width > width || fc.y_offset + fc.height > height {
+            return Err(format!("Frame {} is out of image bounds", i).into());
+        }
+
+        // first frame can't be disposed to previous
+        let dispose = match fc.dispose_op {
+            DisposeOp::Previous if i == 0 => DisposeOp::Background,
+            op => op,
+        };
+        let previous = (dispose == DisposeOp::Previous).then(|| canvas.clone());
+        blend(&mut canvas, &subframe, &fc);
+
+        let img = if high_bit_depth {
+            DynamicImage::ImageRgba16(canvas.clone())
+        } else {
+            DynamicImage::ImageRgba16(canvas.clone())
+                .into_rgba8()
+                .into()
+        };
+        let mut data = Vec::new();
+        img.write_to(&mut Cursor::new(&mut data), ImageFormat::Png)?;
+        frames.push(data);
+
+        let start = elapsed;
+        elapsed += delay_ms(fc.delay_num, fc.delay_den);
+        animation.frames.push(Frame {
+            file: format!("{:04}.png", i + 1),
+            delay: (elapsed.round() - start.round()) as u64,
+        });
+
+        match (dispose, previous) {
+            (DisposeOp::Background, _) => {
+                for y in fc.y_offset..fc.y_offset + fc.height {
+                    for x in fc.x_offset..fc.x_offset + fc.width {
+                        canvas.put_pixel(x, y, Rgba([0; 4]));
+                    }
+                }
+            }
+            (DisposeOp::Previous, Some(previous)) => canvas = previous,
+            _ => (),
+        }
+    }
+    Ok((frames, animation))
+}
+
+/// Frame delay in milliseconds, denominator 0 means 1/100 s
+fn delay_ms(num: u16, den: u16) -> f64 {
+    let den = if den == 0 { 100 } else { den };
+    num as f64 * 1000.0 / den as f64
+}
+
+/// Convert decoded subframe bytes to rgba with 16-bit channels
+fn subframe_to_rgba16(data: &[u8], info: &png::OutputInfo) -> BResult<Canvas> {
+    let (w, h) = (info.width, info.height);
+    let img: Option<DynamicImage> = if info.bit_depth == BitDepth::Sixteen {
+        // png samples are big endian
+        let data: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        match info.color_type {
+            ColorType::Grayscale => {
+                ImageBuffer::from_raw(w, h, data).map(DynamicImage::ImageLuma16)
+            }
+            ColorType::GrayscaleAlpha => {
+                ImageBuffer::from_raw(w, h, data).map(DynamicImage::ImageLumaA16)
+            }
+            ColorType::Rgb => ImageBuffer::from_raw(w, h, data).map(DynamicImage::ImageRgb16),
+            ColorType::Rgba => ImageBuffer::from_raw(w, h, data).map(DynamicImage::ImageRgba16),
+            ColorType::Indexed => None,
+        }
+    } else {
+        let data = data.to_vec();
+        match info.color_type {
+            ColorType::Grayscale => ImageBuffer::from_raw(w, h, data).map(DynamicImage::ImageLuma8),
+            ColorType::GrayscaleAlpha => {
+                ImageBuffer::from_raw(w, h, data).map(DynamicImage::ImageLumaA8)
+            }
+            ColorType::Rgb => ImageBuffer::from_raw(w, h, data).map(DynamicImage::ImageRgb8),
+            ColorType::Rgba => ImageBuffer::from_raw(w, h, data).map(DynamicImage::ImageRgba8),
+            ColorType::Indexed => None,
+        }
+    };
+    Ok(img
+        .ok_or("Unsupported apng frame color type")?
+        .into_rgba16())
+}
+
+/// Draw subframe on canvas at its offset
+fn blend(canvas: &mut Canvas, subframe: &Canvas, fc: &png::FrameControl) {
+    const MAX: f32 = u16::MAX as f32;
+    for (x, y, src) in subframe.enumerate_pixels() {
+        let dst = canvas.get_pixel_mut(x + fc.x_offset, y + fc.y_offset);
+        if fc.blend_op == BlendOp::Source || src.0[3] == u16::MAX {
+            *dst = *src;
+            continue;
+        }
+        if src.0[3] == 0 {
+            continue;
+        }
+        let sa = src.0[3] as f32 / MAX;
+        let da = dst.0[3] as f32 / MAX;
+        let oa = sa + da * (1.0 - sa);
+        for c in 0..3 {
+            let v = (src.0[c] as f32 * sa + dst.0[c] as f32 * da * (1.0 - sa)) / oa;
+            dst.0[c] = v.round() as u16;
+        }
+        dst.0[3] = (oa * MAX).round() as u16;
+    }
+}
@@ -10,6 +10,7 @@ use serde::Deserialize;
 
 use crate::BResult;
 
+pub mod apng2frames;
 pub mod ffmpeg_concat;
 pub mod video;
 pub mod zip2video;
@@ -8,7 +8,7 @@ use std::{
 
 use clap::Args;
 
-use crate::{utils, BResult};
+use crate::{walk::WalkOpt, BResult};
 
 use super::{ffmpeg_demuxer_create_from_files, ffmpeg_run, VideoOpts};
 
@@ -17,6 +17,8 @@ pub struct Opt {
     /// input image paths
     #[arg(required = false, default_value = "./*", display_order = 0)]
     input: Vec<PathBuf>,
+    #[command(flatten)]
+    walk: WalkOpt,
 
     /// video dimensions (e.g: '128x128')
     #[arg(short = 'd')]
@@ -62,12 +64,7 @@ pub struct Opt {
 }
 
 pub fn main(opt: Opt) -> BResult<()> {
-    let mut images = opt.input.to_owned();
-    if images[0].to_string_lossy() == "./*" {
-        images = utils::read_cwd()?;
-        utils::filter_images(&mut images);
-        images.sort_unstable()
-    }
+    let images = opt.walk.expand(&opt.input)?;
 
     let dimm = opt.dimensions.and_then(|s| {
         s.split_once('x')
@@ -185,7 +182,7 @@ fn generate_thumbnail(
     ];
     std::process::Command::new("montage")
         .args(montage_args)
-        .arg(&format!(
+        .arg(format!(
             "{}/capture%02d.png[1-{}]",
             &tmpdir.path().display(),
             &tmpdir.path().read_dir().unwrap().count()
@@ -24,7 +24,7 @@ pub struct Opt {
     /// force overwrite existing file
     #[arg(short = 'y')]
     overwrite: bool,
-    ///
+    /// two-pass video encoding
     #[arg(long)]
     two_pass: Option<bool>,
 }
@@ -97,7 +97,11 @@ fn animdata2demux(opt: &Opt, tempdir: &tempfile::TempDir) -> BResult<Vec<(String
     };
     let json: HashMap<String, serde_json::Value> = serde_json::from_reader(animdata_file)?;
     let json_frames = match animdata_type {
-        1 => json.values().next().unwrap()["frames"].as_array(),
+        // frames at top level or nested in single object
+        1 => match json.get("frames") {
+            Some(frames) => frames.as_array(),
+            None => json.values().next().unwrap()["frames"].as_array(),
+        },
         2 => json["frames"].as_array(),
         _ => return Err("Wrong animdata_type".into()),
     }
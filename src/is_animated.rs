@@ -0,0 +1,168 @@
+use std::{
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use clap::Args;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{is_apng, journal, jxl_header, sniff::ImageFormat, walk::WalkOpt, BResult};
+
+#[derive(Args, Debug, Clone)]
+pub struct Opt {
+    /// input image paths
+    #[arg(required = false, default_value = "./*", display_order = 0)]
+    input: Vec<PathBuf>,
+    #[command(flatten)]
+    walk: WalkOpt,
+    /// move animated images to the directory
+    #[arg(short)]
+    out_dir: Option<PathBuf>,
+    #[arg(long, default_value = "0")]
+    nproc: usize,
+}
+
+pub fn main(opt: Opt) -> BResult<()> {
+    let images = opt.walk.expand(&opt.input)?;
+    if let (Some(out_dir), false) = (&opt.out_dir, journal::dry_run()) {
+        journal::mkdir(out_dir)?;
+    }
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(opt.nproc)
+        .build_global()?;
+
+    images.par_iter().for_each(|img| match is_animated(img) {
+        Ok(animated) => {
+            println!("{}\t{}", animated, img.display());
+            if let (true, Some(out_dir)) = (animated, &opt.out_dir) {
+                if let Err(e) = journal::rename(img, &out_dir.join(img.file_name().unwrap())) {
+                    eprintln!("Error moving image {}: {}", img.display(), e);
+                }
+            }
+        }
+        // skip files that aren't images when input is the current dir
+        Err(_) if opt.input[0].to_string_lossy() == "./*" => (),
+        Err(e) => eprintln!("Error processing image {}: {}", img.display(), e),
+    });
+    Ok(())
+}
+
+/// Check if image has more than one frame, parsing only file headers.
+/// Supports png, gif, webp, jxl and avif, jpeg and bmp are never animated
+pub fn is_animated(path: &Path) -> BResult<bool> {
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    let mut magic = [0u8; 64];
+    let n = reader.read(&mut magic)?;
+    reader.seek(SeekFrom::Start(0))?;
+    match ImageFormat::from_magic(&magic[..n]) {
+        Some(ImageFormat::Png | ImageFormat::Apng) => Ok(is_apng::decode(&mut reader)? >= 2),
+        Some(ImageFormat::Gif) => gif_is_animated(&mut reader),
+        Some(ImageFormat::Webp) => webp_is_animated(&mut reader),
+        Some(ImageFormat::Jxl) => Ok(jxl_header::read_file(path)?.animated),
+        Some(ImageFormat::Avif | ImageFormat::Heif) => isobmff_is_animated(&mut reader),
+        Some(ImageFormat::Jpeg | ImageFormat::Bmp) => Ok(false),
+        None => Err(format!("Unknown image format: {}", path.display()).into()),
+    }
+}
+
+/// Check for image sequence brand in `ftyp` box
+fn isobmff_is_animated(reader: &mut impl Read) -> BResult<bool> {
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header)?;
+    let size = u32::from_be_bytes(header[0..4].try_into().unwrap());
+    let mut ftyp = Vec::new();
+    reader
+        .take(size.saturating_sub(8).min(1024) as u64)
+        .read_to_end(&mut ftyp)?;
+    Ok(ftyp
+        .chunks_exact(4)
+        .any(|brand| brand == b"avis" || brand == b"msf1"))
+}
+
+/// Check animation flag of `VP8X` chunk or presence of `ANIM` / `ANMF` chunks
+fn webp_is_animated(reader: &mut (impl Read + Seek)) -> BResult<bool> {
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WEBP" {
+        return Err("Not a webp file".into());
+    }
+    let mut chunk_header = [0u8; 8];
+    while reader.read_exact(&mut chunk_header).is_ok() {
+        let length = u32::from_le_bytes(chunk_header[4..8].try_into().unwrap());
+        match &chunk_header[0..4] {
+            b"VP8X" => {
+                let mut flags = [0u8; 1];
+                reader.read_exact(&mut flags)?;
+                // animation flag
+                if flags[0] & 0x02 != 0 {
+                    return Ok(true);
+                }
+                reader.seek(SeekFrom::Current((length + length % 2) as i64 - 1))?;
+            }
+            b"ANIM" | b"ANMF" => return Ok(true),
+            b"VP8 " | b"VP8L" => return Ok(false),
+            _ => {
+                reader.seek(SeekFrom::Current((length + length % 2) as i64))?;
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Check for more than one image descriptor
+fn gif_is_animated(reader: &mut (impl Read + Seek)) -> BResult<bool> {
+    // signature 6 + logical screen descriptor 7
+    let mut header = [0u8; 13];
+    reader.read_exact(&mut header)?;
+    skip_color_table(reader, header[10])?;
+    let mut images = 0;
+    loop {
+        let mut introducer = [0u8; 1];
+        if reader.read_exact(&mut introducer).is_err() {
+            // truncated file
+            return Ok(false);
+        }
+        match introducer[0] {
+            // image descriptor
+            0x2c => {
+                images += 1;
+                if images >= 2 {
+                    return Ok(true);
+                }
+                let mut descriptor = [0u8; 9];
+                reader.read_exact(&mut descriptor)?;
+                skip_color_table(reader, descriptor[8])?;
+                // lzw minimum code size
+                reader.seek(SeekFrom::Current(1))?;
+                skip_sub_blocks(reader)?;
+            }
+            // extension
+            0x21 => {
+                reader.seek(SeekFrom::Current(1))?;
+                skip_sub_blocks(reader)?;
+            }
+            // trailer
+            0x3b => return Ok(false),
+            _ => return Err("Invalid gif block".into()),
+        }
+    }
+}
+
+fn skip_color_table(reader: &mut impl Seek, flags: u8) -> BResult<()> {
+    if flags & 0x80 != 0 {
+        let size = 3 * (1 << ((flags & 0x07) + 1));
+        reader.seek(SeekFrom::Current(size))?;
+    }
+    Ok(())
+}
+
+fn skip_sub_blocks(reader: &mut (impl Read + Seek)) -> BResult<()> {
+    loop {
+        let mut size = [0u8; 1];
+        reader.read_exact(&mut size)?;
+        if size[0] == 0 {
+            return Ok(());
+        }
+        reader.seek(SeekFrom::Current(size[0] as i64))?;
+    }
+}
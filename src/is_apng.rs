@@ -1,9 +1,12 @@
 use std::{
     io::{BufReader, Read, Seek},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use clap::Args;
+use serde::Serialize;
+
+use crate::{sniff::ImageFormat, walk::WalkOpt, BResult};
 
 type Result<T> = std::io::Result<T>;
 
@@ -12,66 +15,284 @@ const CRC_LENGTH: u32 = 4;
 
 #[derive(Args, Debug, Clone)]
 pub struct Opt {
-    input: PathBuf,
+    /// input png paths
+    #[arg(required = false, default_value = "./*", display_order = 0)]
+    input: Vec<PathBuf>,
+    #[command(flatten)]
+    walk: WalkOpt,
+    /// pretty print json
+    #[arg(short, long)]
+    pretty: bool,
 }
 
-pub fn main(opt: Opt) -> Result<()> {
-    let f = std::fs::File::open(opt.input)?;
-    let mut br = BufReader::new(f);
-    if decode(&mut br)? {
-        println!("true");
-    } else {
-        println!("false");
-    };
-
+pub fn main(opt: Opt) -> BResult<()> {
+    let images = opt
+        .walk
+        .expand_formats(&opt.input, &[ImageFormat::Png, ImageFormat::Apng])?;
+    for path in images {
+        let info = ApngInfo::read(&path)?;
+        let file_info = FileInfo { file: &path, info };
+        let json = if opt.pretty {
+            serde_json::to_string_pretty(&file_info)?
+        } else {
+            serde_json::to_string(&file_info)?
+        };
+        println!("{}", json);
+    }
     Ok(())
 }
 
+#[derive(Serialize)]
+struct FileInfo<'a> {
+    file: &'a Path,
+    #[serde(flatten)]
+    info: ApngInfo,
+}
+
 #[derive(Debug)]
-struct ChunkHeaderData {
-    length: u32,
-    typ: [u8; 4],
+pub(crate) struct ChunkHeaderData {
+    pub length: u32,
+    pub typ: [u8; 4],
 }
 
 impl ChunkHeaderData {
-    fn new(reader: &mut (impl Read + Seek)) -> Result<Self> {
+    pub fn new(reader: &mut (impl Read + Seek)) -> Result<Self> {
         let length = read_uint32(reader)?;
         let mut t = [0, 0, 0, 0];
         reader.read_exact(&mut t)?;
         Ok(Self { length, typ: t })
     }
+
+    /// Read chunk data and skip its crc
+    fn read_data(&self, reader: &mut (impl Read + Seek)) -> Result<Vec<u8>> {
+        let mut data = vec![0; self.length as usize];
+        reader.read_exact(&mut data)?;
+        reader.seek(std::io::SeekFrom::Current(CRC_LENGTH as i64))?;
+        Ok(data)
+    }
+
+    fn skip(&self, reader: &mut (impl Read + Seek)) -> Result<()> {
+        reader.seek(std::io::SeekFrom::Current(
+            (self.length + CRC_LENGTH) as i64,
+        ))?;
+        Ok(())
+    }
 }
 
-fn read_uint32(reader: &mut (impl Read + Seek)) -> Result<u32> {
+pub(crate) fn read_uint32(reader: &mut (impl Read + Seek)) -> Result<u32> {
     let mut u = [0, 0, 0, 0];
     reader.read_exact(&mut u)?;
     Ok(u32::from_be_bytes(u))
 }
 
-fn decode(reader: &mut (impl Read + Seek)) -> Result<bool> {
+fn invalid_data(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+pub(crate) fn read_png_header(reader: &mut (impl Read + Seek)) -> Result<()> {
     let mut header = [0u8; PNG_HEADER.len()];
     reader.read_exact(&mut header)?;
     if header != PNG_HEADER {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "PNG header not found",
-        ));
+        return Err(invalid_data("PNG header not found"));
     }
+    Ok(())
+}
+
+/// Check if png file is animated
+pub fn is_apng(path: &Path) -> Result<bool> {
+    let f = std::fs::File::open(path)?;
+    Ok(decode(&mut BufReader::new(f))? >= 2)
+}
 
+/// Number of frames from `acTL` chunk, 1 if png has no `acTL` before `IDAT`
+pub fn decode(reader: &mut (impl Read + Seek)) -> Result<u32> {
+    read_png_header(reader)?;
     loop {
         let chd = ChunkHeaderData::new(reader)?;
-        // debug!("{}, {}", core::str::from_utf8(&chd.t).unwrap(), chd.length);
         match &chd.typ {
             b"acTL" => {
-                let length = read_uint32(reader)?;
-                return Ok(length >= 2);
+                let num_frames = read_uint32(reader)?;
+                return Ok(num_frames);
             }
             b"IDAT" => {
-                return Ok(false);
+                return Ok(1);
             }
-            _ => {
-                reader.seek(std::io::SeekFrom::Current((chd.length + CRC_LENGTH) as i64))?;
+            _ => chd.skip(reader)?,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DisposeOp {
+    /// frame area is left as is
+    None,
+    /// frame area is cleared to transparent black
+    Background,
+    /// frame area is reverted to the previous contents
+    Previous,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BlendOp {
+    /// frame replaces the area
+    Source,
+    /// frame is alpha composited over the area
+    Over,
+}
+
+/// Frame control (`fcTL`) chunk
+#[derive(Serialize, Debug, Clone)]
+pub struct FrameControl {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub delay_num: u16,
+    pub delay_den: u16,
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+}
+
+impl FrameControl {
+    fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() != 26 {
+            return Err(invalid_data("Invalid fcTL chunk length"));
+        }
+        let u32_at = |i: usize| u32::from_be_bytes(data[i..i + 4].try_into().unwrap());
+        let u16_at = |i: usize| u16::from_be_bytes(data[i..i + 2].try_into().unwrap());
+        Ok(Self {
+            sequence_number: u32_at(0),
+            width: u32_at(4),
+            height: u32_at(8),
+            x_offset: u32_at(12),
+            y_offset: u32_at(16),
+            delay_num: u16_at(20),
+            delay_den: u16_at(22),
+            dispose_op: match data[24] {
+                0 => DisposeOp::None,
+                1 => DisposeOp::Background,
+                2 => DisposeOp::Previous,
+                _ => return Err(invalid_data("Invalid fcTL dispose op")),
+            },
+            blend_op: match data[25] {
+                0 => BlendOp::Source,
+                1 => BlendOp::Over,
+                _ => return Err(invalid_data("Invalid fcTL blend op")),
+            },
+        })
+    }
+
+    /// Frame delay in milliseconds, denominator 0 means 1/100 s
+    pub fn delay_ms(&self) -> f64 {
+        let den = if self.delay_den == 0 {
+            100
+        } else {
+            self.delay_den
+        };
+        self.delay_num as f64 * 1000.0 / den as f64
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FrameInfo {
+    #[serde(flatten)]
+    pub control: FrameControl,
+    /// delay in milliseconds
+    pub delay: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ApngInfo {
+    pub width: u32,
+    pub height: u32,
+    pub animated: bool,
+    /// number of frames declared in `acTL`, 1 for still png
+    pub num_frames: u32,
+    /// number of times to loop, 0 is infinite
+    pub num_plays: u32,
+    /// `IDAT` image is the first frame of the animation
+    pub default_image_is_frame: bool,
+    /// total duration in milliseconds
+    pub duration: f64,
+    pub frames: Vec<FrameInfo>,
+}
+
+impl ApngInfo {
+    pub fn read(path: &Path) -> Result<Self> {
+        let f = std::fs::File::open(path)?;
+        Self::parse(&mut BufReader::new(f))
+    }
+
+    /// Walk all chunks up to `IEND`
+    pub fn parse(reader: &mut (impl Read + Seek)) -> Result<Self> {
+        read_png_header(reader)?;
+        let (mut width, mut height) = (0, 0);
+        let mut actl = None;
+        let mut frames: Vec<FrameInfo> = Vec::new();
+        let mut seen_idat = false;
+        let mut default_image_is_frame = false;
+        loop {
+            let chd = ChunkHeaderData::new(reader)?;
+            match &chd.typ {
+                b"IHDR" => {
+                    let data = chd.read_data(reader)?;
+                    if data.len() != 13 {
+                        return Err(invalid_data("Invalid IHDR chunk length"));
+                    }
+                    width = u32::from_be_bytes(data[0..4].try_into().unwrap());
+                    height = u32::from_be_bytes(data[4..8].try_into().unwrap());
+                }
+                // acTL after IDAT is ignored
+                b"acTL" if !seen_idat => {
+                    let data = chd.read_data(reader)?;
+                    if data.len() != 8 {
+                        return Err(invalid_data("Invalid acTL chunk length"));
+                    }
+                    actl = Some((
+                        u32::from_be_bytes(data[0..4].try_into().unwrap()),
+                        u32::from_be_bytes(data[4..8].try_into().unwrap()),
+                    ));
+                }
+                b"fcTL" => {
+                    let control = FrameControl::parse(&chd.read_data(reader)?)?;
+                    if !seen_idat {
+                        default_image_is_frame = true;
+                    }
+                    frames.push(FrameInfo {
+                        delay: control.delay_ms(),
+                        control,
+                    });
+                }
+                b"IDAT" => {
+                    seen_idat = true;
+                    chd.skip(reader)?;
+                }
+                b"IEND" => break,
+                _ => chd.skip(reader)?,
             }
         }
+        if !seen_idat {
+            return Err(invalid_data("IDAT chunk not found"));
+        }
+
+        let (num_frames, num_plays) = actl.unwrap_or((1, 0));
+        // without acTL the png is a still image and fcTL chunks are ignored
+        if actl.is_none() {
+            frames.clear();
+            default_image_is_frame = false;
+        }
+        Ok(Self {
+            width,
+            height,
+            animated: num_frames >= 2,
+            num_frames,
+            num_plays,
+            default_image_is_frame,
+            duration: frames.iter().fold(0.0, |acc, f| acc + f.delay),
+            frames,
+        })
     }
 }
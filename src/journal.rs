@@ -0,0 +1,333 @@
+use std::{
+    collections::BTreeSet,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+use crate::BResult;
+
+#[derive(Args, Debug, Clone)]
+pub struct UndoOpt {
+    /// journal written with '--journal'
+    input: PathBuf,
+}
+
+/// File operation, one json line in journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Entry {
+    /// File was moved, undo moves it back
+    Move { from: PathBuf, to: PathBuf },
+    /// New file was created, undo removes it
+    Create { path: PathBuf },
+    /// New directory was created, undo removes it if empty
+    Mkdir { path: PathBuf },
+    /// File identical to `same_as` was removed, undo copies it back
+    Remove { path: PathBuf, same_as: PathBuf },
+}
+
+/// What to do when destination file already exists
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnConflict {
+    /// keep source in place
+    Skip,
+    /// add numeric suffix to destination name
+    Rename,
+    /// replace destination with the same content, error otherwise
+    Identical,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Journal {
+    path: PathBuf,
+}
+
+/// Global `--dry-run`, `--journal` and `--on-conflict` options
+struct Global {
+    dry_run: bool,
+    journal: Option<Journal>,
+    on_conflict: OnConflict,
+}
+
+static GLOBAL: OnceLock<Global> = OnceLock::new();
+/// Serializes appends from parallel workers
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+/// Destinations picked by parallel workers and not created yet, in dry run
+/// they are kept to the end as files are never created
+static RESERVED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+/// Set global options, called once at startup
+pub fn init(journal: Option<&Path>, dry_run: bool, on_conflict: OnConflict) {
+    let _ = GLOBAL.set(Global {
+        dry_run,
+        journal: journal.map(Journal::new),
+        on_conflict,
+    });
+}
+
+fn on_conflict() -> OnConflict {
+    GLOBAL.get().map_or(OnConflict::Rename, |g| g.on_conflict)
+}
+
+/// Print planned file operations instead of performing them
+pub fn dry_run() -> bool {
+    GLOBAL.get().is_some_and(|g| g.dry_run)
+}
+
+/// Operations are recorded to global journal
+pub fn recording() -> bool {
+    GLOBAL.get().is_some_and(|g| g.journal.is_some()) && !dry_run()
+}
+
+/// Append entry to global journal, if any
+pub fn record(entry: Entry) -> BResult<()> {
+    match GLOBAL.get().and_then(|g| g.journal.as_ref()) {
+        Some(j) if !dry_run() => j.push(entry),
+        _ => Ok(()),
+    }
+}
+
+/// Create directory with parents and record it if it didn't exist
+pub fn mkdir(dir: &Path) -> BResult<()> {
+    if dir.exists() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(dir)?;
+    record(Entry::Mkdir {
+        path: dir.to_path_buf(),
+    })
+}
+
+/// Move file with conflict policy and record it, only print the move in dry run.
+/// Returns final destination, None if skipped
+pub fn rename(from: &Path, to: &Path) -> BResult<Option<PathBuf>> {
+    place(from, to, |dest| {
+        if dry_run() {
+            println!("{} --> {}", from.display(), dest.display());
+            return Ok(());
+        }
+        move_file(from, dest)?;
+        record(Entry::Move {
+            from: from.to_path_buf(),
+            to: dest.to_path_buf(),
+        })
+    })
+}
+
+/// Keep file as `backup` before it is replaced or removed, journaled for undo
+pub fn backup(path: &Path, backup: &Path) -> BResult<()> {
+    place(path, backup, |backup| {
+        if std::fs::hard_link(path, backup).is_err() {
+            std::fs::copy(path, backup)?;
+        }
+        record(Entry::Move {
+            from: path.to_path_buf(),
+            to: backup.to_path_buf(),
+        })
+    })?
+    // file is replaced or removed after backup
+    .ok_or("Backup already exists, keeping original")?;
+    Ok(())
+}
+
+/// Resolve destination conflict and run `op` with the free destination.
+/// Lock is held only to pick and reserve destination, `op` runs in parallel.
+/// Identical destination is removed first and recorded to be restored by undo.
+/// Returns final destination, None if skipped
+pub fn place(
+    from: &Path,
+    to: &Path,
+    op: impl FnOnce(&Path) -> BResult<()>,
+) -> BResult<Option<PathBuf>> {
+    let dest = {
+        let mut reserved = RESERVED.lock().unwrap();
+        let Some(dest) = resolve(from, to, &reserved)? else {
+            return Ok(None);
+        };
+        reserved.insert(dest.clone());
+        dest
+    };
+    let res = (|| {
+        if !dry_run() && dest.symlink_metadata().is_ok() {
+            record(Entry::Remove {
+                path: dest.clone(),
+                same_as: from.to_path_buf(),
+            })?;
+            std::fs::remove_file(&dest)?;
+        }
+        op(&dest)
+    })();
+    if !dry_run() {
+        RESERVED.lock().unwrap().remove(&dest);
+    }
+    res.map(|_| Some(dest))
+}
+
+/// Destination for `from` according to conflict policy, None to skip.
+/// Reserved destinations are taken even if they don't exist yet
+fn resolve(from: &Path, to: &Path, reserved: &BTreeSet<PathBuf>) -> BResult<Option<PathBuf>> {
+    let taken = |p: &Path| reserved.contains(p) || p.symlink_metadata().is_ok();
+    if !taken(to) {
+        return Ok(Some(to.to_path_buf()));
+    }
+    let is_reserved = reserved.contains(to);
+    if !is_reserved && from.canonicalize()? == to.canonicalize()? {
+        println!("Skip, already in place: {}", to.display());
+        return Ok(None);
+    }
+    match on_conflict() {
+        OnConflict::Skip => {
+            println!("Skip, destination exists: {}", to.display());
+            Ok(None)
+        }
+        OnConflict::Rename => {
+            let stem = to.file_stem().unwrap_or_default().to_string_lossy();
+            let ext = to
+                .extension()
+                .map(|e| format!(".{}", e.to_string_lossy()))
+                .unwrap_or_default();
+            Ok((1..)
+                .map(|n| to.with_file_name(format!("{}-{}{}", stem, n, ext)))
+                .find(|p| !taken(p)))
+        }
+        OnConflict::Identical if !is_reserved && same_content(from, to)? => {
+            Ok(Some(to.to_path_buf()))
+        }
+        OnConflict::Identical | OnConflict::Error => {
+            Err(format!("Destination already exists: {}", to.display()).into())
+        }
+    }
+}
+
+/// Rename file, across filesystems copy it, verify the copy and remove source
+pub fn move_file(from: &Path, to: &Path) -> BResult<()> {
+    match std::fs::rename(from, to) {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => (),
+        res => return Ok(res?),
+    }
+    let dir = to.parent().filter(|p| !p.as_os_str().is_empty());
+    let tmp = tempfile::NamedTempFile::new_in(dir.unwrap_or(Path::new(".")))?;
+    std::fs::copy(from, tmp.path())?;
+    tmp.as_file().set_modified(from.metadata()?.modified()?)?;
+    tmp.as_file().sync_all()?;
+    if !same_content(from, tmp.path())? {
+        return Err(format!("Copy differs from source: {}", from.display()).into());
+    }
+    tmp.persist(to)?;
+    std::fs::remove_file(from)?;
+    Ok(())
+}
+
+/// Compare files byte by byte
+pub fn same_content(a: &Path, b: &Path) -> std::io::Result<bool> {
+    let (mut fa, mut fb) = (File::open(a)?, File::open(b)?);
+    let mut len = fa.metadata()?.len();
+    if len != fb.metadata()?.len() {
+        return Ok(false);
+    }
+    let (mut buf_a, mut buf_b) = (vec![0u8; 1 << 16], vec![0u8; 1 << 16]);
+    while len > 0 {
+        let n = len.min(buf_a.len() as u64) as usize;
+        fa.read_exact(&mut buf_a[..n])?;
+        fb.read_exact(&mut buf_b[..n])?;
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+        len -= n as u64;
+    }
+    Ok(true)
+}
+
+impl Journal {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_owned(),
+        }
+    }
+
+    /// Append entry with absolute paths and flush it to disk
+    pub fn push(&self, entry: Entry) -> BResult<()> {
+        let entry = match entry {
+            Entry::Move { from, to } => Entry::Move {
+                from: std::path::absolute(from)?,
+                to: std::path::absolute(to)?,
+            },
+            Entry::Create { path } => Entry::Create {
+                path: std::path::absolute(path)?,
+            },
+            Entry::Mkdir { path } => Entry::Mkdir {
+                path: std::path::absolute(path)?,
+            },
+            Entry::Remove { path, same_as } => Entry::Remove {
+                path: std::path::absolute(path)?,
+                same_as: std::path::absolute(same_as)?,
+            },
+        };
+        let _lock = WRITE_LOCK.lock().unwrap();
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        f.write_all((serde_json::to_string(&entry)? + "\n").as_bytes())?;
+        f.sync_all()?;
+        Ok(())
+    }
+}
+
+pub fn main(opt: UndoOpt) -> BResult<()> {
+    undo(&opt.input)
+}
+
+/// Revert journal entries in reverse order and remove the journal
+pub fn undo(journal: &Path) -> BResult<()> {
+    let reader = BufReader::new(File::open(journal)?);
+    let entries = reader
+        .lines()
+        .filter(|l| l.as_ref().map_or(true, |l| !l.trim().is_empty()))
+        .map(|l| Ok(serde_json::from_str::<Entry>(&l?)?))
+        .collect::<BResult<Vec<_>>>()?;
+
+    for entry in entries.iter().rev() {
+        match entry {
+            Entry::Move { from, to } => {
+                println!("{} --> {}", to.display(), from.display());
+                if !dry_run() {
+                    if let Some(dir) = from.parent() {
+                        std::fs::create_dir_all(dir)?;
+                    }
+                    move_file(to, from)?;
+                }
+            }
+            Entry::Create { path } => {
+                println!("rm {}", path.display());
+                // symlink_metadata keeps dangling symlinks
+                if !dry_run() && path.symlink_metadata().is_ok() {
+                    std::fs::remove_file(path)?;
+                }
+            }
+            Entry::Mkdir { path } => {
+                println!("rmdir {}", path.display());
+                // directory may be already removed or have new files
+                if !dry_run() && std::fs::remove_dir(path).is_err() {
+                    println!("Can't remove dir: {}", path.display());
+                }
+            }
+            Entry::Remove { path, same_as } => {
+                println!("cp {} --> {}", same_as.display(), path.display());
+                if !dry_run() {
+                    std::fs::copy(same_as, path)?;
+                }
+            }
+        }
+    }
+    if !dry_run() {
+        std::fs::remove_file(journal)?;
+    }
+    Ok(())
+}
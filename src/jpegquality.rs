@@ -1,121 +1,735 @@
 // Based on jpegquality by Neal Krawetz
 
-use std::{
-    fs::File,
-    io::{BufReader, Bytes, Read},
-    path::Path,
-};
+use std::path::{Path, PathBuf};
 
-use crate::BResult;
+use clap::Args;
+use rayon::prelude::*;
+use serde::Serialize;
 
-pub fn jpeg_quality(filepath: &Path) -> BResult<f32> {
-    let file = std::fs::File::open(filepath)?;
-    let mut reader = std::io::BufReader::new(file).bytes();
+use crate::{sniff::ImageFormat, walk::WalkOpt, BResult};
+
+#[derive(Args, Debug, Clone)]
+pub struct Opt {
+    /// input jpeg paths
+    #[arg(required = false, default_value = "./*", display_order = 0)]
+    input: Vec<PathBuf>,
+    #[command(flatten)]
+    walk: WalkOpt,
+    /// pretty print json
+    #[arg(short, long)]
+    pretty: bool,
+    /// search traces of previous compression, slow
+    #[arg(short, long)]
+    recompression: bool,
+}
 
-    let mut header = [0; 15];
-    for h in &mut header {
-        *h = reader.next().unwrap()?;
+/// Print jpeg info of every input as one json line
+pub fn main(opt: Opt) -> BResult<()> {
+    let images = opt.walk.expand_formats(&opt.input, &[ImageFormat::Jpeg])?;
+    for path in images {
+        let mut info = JpegInfo::read(&path)?;
+        if opt.recompression {
+            info.detect_recompression(&std::fs::read(&path)?)?;
+        }
+        let json = if opt.pretty {
+            serde_json::to_string_pretty(&FileInfo { file: &path, info })?
+        } else {
+            serde_json::to_string(&FileInfo { file: &path, info })?
+        };
+        println!("{}", json);
     }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct FileInfo<'a> {
+    file: &'a Path,
+    #[serde(flatten)]
+    info: JpegInfo,
+}
+
+/// IJG (libjpeg) standard luminance quantization table, natural order
+const STD_LUMINANCE: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113,
+    92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+/// IJG (libjpeg) standard chrominance quantization table, natural order
+const STD_CHROMINANCE: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+/// mozjpeg default quantization table (ImageMagick table used for both luma and chroma),
+/// natural order
+const MOZJPEG_TABLE: [u16; 64] = [
+    16, 16, 16, 18, 25, 37, 56, 85, 16, 17, 20, 27, 34, 40, 53, 75, 16, 20, 24, 31, 43, 62, 91,
+    135, 18, 27, 31, 40, 53, 74, 106, 156, 25, 34, 43, 53, 69, 94, 131, 189, 37, 40, 62, 74, 94,
+    124, 169, 238, 56, 53, 91, 106, 131, 169, 226, 311, 85, 75, 135, 156, 189, 238, 311, 418,
+];
+
+/// Natural order index of zigzag position
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// Quality estimate from average of quantization table values
+pub fn jpeg_quality(filepath: &Path) -> BResult<f32> {
+    Ok(JpegInfo::read(filepath)?.estimated_quality)
+}
 
-    if (header[0] != 0xFF) || (header[1] != 0xD8) {
-        return Err("Not a supported JPEG file".into());
+/// Encoder family guessed from quantization tables and markers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoder {
+    /// IJG standard tables scaled by quality: libjpeg, libjpeg-turbo and most software
+    Libjpeg,
+    /// mozjpeg default tables scaled by quality
+    Mozjpeg,
+    /// custom tables with Photoshop resource and Adobe markers
+    Photoshop,
+    /// custom tables with exif, most likely camera firmware
+    Camera,
+    #[default]
+    Unknown,
+}
+
+/// Structure of a jpeg file
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct JpegInfo {
+    pub width: u16,
+    pub height: u16,
+    /// "baseline", "extended", "progressive" or "lossless"
+    pub process: String,
+    pub progressive: bool,
+    pub arithmetic: bool,
+    pub components: Vec<Component>,
+    /// e.g. "4:2:0", "gray", or "HxV" of luma for unusual layouts
+    pub subsampling: String,
+    /// restart interval in MCUs, 0 if not set
+    pub restart_interval: u16,
+    pub quant_tables: Vec<QuantTable>,
+    /// IJG quality of luminance table
+    pub ijg_quality: Option<u8>,
+    /// average based estimate used by `convert`
+    pub estimated_quality: f32,
+    pub jfif: bool,
+    pub exif: bool,
+    pub icc: bool,
+    pub adobe: bool,
+    /// Photoshop image resources (APP13)
+    pub photoshop: bool,
+    /// primary image ends with EOI marker, missing in truncated files
+    pub eoi: bool,
+    /// bytes after EOI without padding, e.g. MPF images or vendor trailers
+    pub trailer: usize,
+    pub encoder: Encoder,
+    /// IJG quality of earlier compression found by `detect_recompression`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary_quality: Option<u8>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Component {
+    pub id: u8,
+    pub h_sampling: u8,
+    pub v_sampling: u8,
+    pub quant_table: u8,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QuantTable {
+    pub index: u8,
+    /// 8 or 16 bit values
+    pub precision: u8,
+    /// values in natural (row-major) order
+    pub values: Vec<u16>,
+    /// closest IJG quality for the standard table of this slot
+    pub ijg_quality: u8,
+    /// table is exactly the scaled standard table
+    pub ijg_exact: bool,
+}
+
+impl JpegInfo {
+    pub fn read(path: &Path) -> BResult<Self> {
+        Self::parse(&std::fs::read(path)?)
     }
 
-    let mut quality_avg = [0.0; 3];
-    loop {
-        let marker = read_jpeg_marker(&mut reader)?;
-        if marker == 0xff00 {
-            return Ok(quality_avg[0]);
+    pub fn parse(data: &[u8]) -> BResult<Self> {
+        if !data.starts_with(&[0xff, 0xd8]) {
+            return Err("Not a supported JPEG file".into());
+        }
+        let mut info = Self::default();
+        let mut segments = Segments::new(data);
+        for segment in segments.by_ref() {
+            let segment = segment?;
+            info.read_segment(segment.marker, segment.payload)?;
+        }
+        info.eoi = segments.eoi;
+        if info.eoi {
+            info.trailer = data[segments.pos..]
+                .iter()
+                .rposition(|&b| b != 0x00 && b != 0xff)
+                .map_or(0, |p| p + 1);
+        }
+        if info.components.is_empty() {
+            return Err("No frame header".into());
         }
-        let mut box_len = reader.next().unwrap()? as usize * 256 + reader.next().unwrap()? as usize;
+        if info.process != "lossless" {
+            if let Some(c) = info
+                .components
+                .iter()
+                .find(|c| info.quant_tables.iter().all(|t| t.index != c.quant_table))
+            {
+                return Err(format!("Missing quantization table {}", c.quant_table).into());
+            }
+        }
+        info.subsampling = subsampling(&info.components);
+        info.ijg_quality = info
+            .quant_tables
+            .iter()
+            .find(|t| t.index == 0)
+            .map(|t| t.ijg_quality);
+        info.estimated_quality = estimated_quality(&info.quant_tables);
+        info.encoder = info.fingerprint();
+        Ok(info)
+    }
 
-        if box_len >= 3 {
-            box_len -= 2;
+    /// Average based estimate of IJG tables with `quality` in place of the file tables,
+    /// compares IJG quality with `estimated_quality`
+    pub fn estimated_quality_at(&self, quality: u8) -> f32 {
+        let tables = self
+            .quant_tables
+            .iter()
+            .map(|t| QuantTable {
+                values: ijg_table(ijg_standard(t.index), quality).to_vec(),
+                ijg_quality: quality,
+                ijg_exact: true,
+                ..t.clone()
+            })
+            .collect::<Vec<_>>();
+        estimated_quality(&tables)
+    }
+
+    fn fingerprint(&self) -> Encoder {
+        if self.quant_tables.iter().all(|t| t.ijg_exact) {
+            Encoder::Libjpeg
+        } else if self
+            .quant_tables
+            .iter()
+            .all(|t| table_match(&t.values, &MOZJPEG_TABLE).1)
+        {
+            Encoder::Mozjpeg
+        } else if self.photoshop && self.adobe {
+            Encoder::Photoshop
+        } else if self.exif {
+            Encoder::Camera
         } else {
-            continue;
-        }
-        if marker != 0xffdb {
-            reader.nth(box_len - 1);
-            continue;
-        }
-
-        if box_len % 65 != 0 {
-            return Err("Wrong size for quantization table".into());
-        }
-
-        while box_len > 0 {
-            let precision = reader.next().unwrap()?;
-            box_len -= 1;
-            let index = precision & 0x0f;
-            // precision = (precision & 0xf0) / 16;
-            // println!(
-            //     "  Precision: {}; Table index: {} ({})\n",
-            //     precision,
-            //     index,
-            //     if index > 0 {
-            //         "chrominance"
-            //     } else {
-            //         "luminance"
-            //     }
-            // );
-
-            let mut total: usize = 0;
-            let mut total_num = 0;
-
-            while (box_len > 0) && (total_num < 64) {
-                let i = reader.next().unwrap()?;
-                if total_num != 0 {
-                    total += i as usize;
-                }
-                box_len -= 1;
-                total_num += 1;
+            Encoder::Unknown
+        }
+    }
+
+    /// Search previous compression with lower quality by the "JPEG ghost" method:
+    /// recompressing a double compressed image at its primary quality gives a local
+    /// error minimum. Only for images with IJG tables, sets `primary_quality`
+    pub fn detect_recompression(&mut self, data: &[u8]) -> BResult<()> {
+        let Some(quality) = self
+            .ijg_quality
+            .filter(|_| self.encoder == Encoder::Libjpeg)
+        else {
+            return Ok(());
+        };
+        let img = image::load_from_memory_with_format(data, image::ImageFormat::Jpeg)?.into_luma8();
+        // block aligned crop keeps the original 8x8 grid
+        let (w, h) = (img.width().min(512) / 8 * 8, img.height().min(512) / 8 * 8);
+        if w < 64 || h < 64 {
+            return Ok(());
+        }
+        let crop = image::imageops::crop_imm(&img, 0, 0, w, h).to_image();
+        let errors = (GHOST_MIN_QUALITY..quality)
+            .step_by(GHOST_STEP as usize)
+            .collect::<Vec<_>>()
+            .par_iter()
+            .map(|&q| Ok((q, recompression_error(&crop, q)?)))
+            .collect::<BResult<Vec<(u8, f64)>>>()?;
+
+        // deepest local minimum followed by a rise of the error
+        self.primary_quality = (1..errors.len().saturating_sub(1))
+            .filter(|&i| errors[i].0 + GHOST_MARGIN < quality)
+            .filter(|&i| errors[i].1 < errors[i - 1].1 && errors[i].1 <= errors[i + 1].1)
+            .map(|i| {
+                let peak = errors[i + 1..].iter().map(|e| e.1).fold(0.0, f64::max);
+                (errors[i].0, peak / errors[i].1)
+            })
+            .filter(|&(_, rise)| rise >= GHOST_RISE)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(q, _)| q);
+        Ok(())
+    }
+
+    fn read_segment(&mut self, marker: u8, payload: &[u8]) -> BResult<()> {
+        match marker {
+            0xe0 if payload.starts_with(b"JFIF\0") => self.jfif = true,
+            0xe1 if payload.starts_with(b"Exif\0") => self.exif = true,
+            0xe2 if payload.starts_with(b"ICC_PROFILE\0") => self.icc = true,
+            0xee if payload.starts_with(b"Adobe") => self.adobe = true,
+            0xed if payload.starts_with(b"Photoshop 3.0\0") => self.photoshop = true,
+            0xdb => self.read_dqt(payload)?,
+            0xdd => {
+                let interval = payload
+                    .get(..2)
+                    .ok_or("Restart interval segment too short")?;
+                self.restart_interval = u16::from_be_bytes([interval[0], interval[1]])
             }
-            total_num -= 1;
-            if index < 3 {
-                quality_avg[index as usize] = 100.0 - total as f32 / total_num as f32;
-                // println!("Esitmated quality level: {}", quality_avg[index as usize]);
-                for i in (index + 1)..3 {
-                    quality_avg[i as usize] = quality_avg[index as usize];
-                }
+            0xc0..=0xcf if ![0xc4, 0xc8, 0xcc].contains(&marker) => {
+                self.read_sof(marker, payload)?
             }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn read_sof(&mut self, marker: u8, payload: &[u8]) -> BResult<()> {
+        let num_components = *payload.get(5).ok_or("Frame header too short")? as usize;
+        if payload.len() < 6 + 3 * num_components || num_components == 0 {
+            return Err("Frame header too short".into());
+        }
+        self.height = u16::from_be_bytes([payload[1], payload[2]]);
+        self.width = u16::from_be_bytes([payload[3], payload[4]]);
+        self.process = match marker & 0x03 {
+            0 if marker == 0xc0 => "baseline",
+            0 | 1 => "extended",
+            2 => "progressive",
+            _ => "lossless",
+        }
+        .to_string();
+        self.progressive = marker & 0x03 == 2;
+        self.arithmetic = marker >= 0xc9;
+        self.components = payload[6..]
+            .chunks_exact(3)
+            .take(num_components)
+            .map(|c| Component {
+                id: c[0],
+                h_sampling: c[1] >> 4,
+                v_sampling: c[1] & 0x0f,
+                quant_table: c[2],
+            })
+            .collect();
+        Ok(())
+    }
 
-            if index > 0 {
-                let diff = (quality_avg[0] - quality_avg[1]).abs() * 0.49
-                    + (quality_avg[0] - quality_avg[2]).abs() * 0.49;
-                let quality_f = (quality_avg[0] + quality_avg[1] + quality_avg[2]) / 3.0 + diff;
-                // println!("Average quality: {}", quality_f);
-                return Ok(quality_f);
+    fn read_dqt(&mut self, mut payload: &[u8]) -> BResult<()> {
+        while let Some((&pq_tq, rest)) = payload.split_first() {
+            let precision = match pq_tq >> 4 {
+                0 => 8,
+                1 => 16,
+                p => return Err(format!("Invalid quantization table precision {}", p).into()),
+            };
+            let index = pq_tq & 0x0f;
+            if index > 3 {
+                return Err(format!("Invalid quantization table index {}", index).into());
             }
+            let size = 64 * precision as usize / 8;
+            let table = rest.get(..size).ok_or("Quantization table too short")?;
+            let mut values = vec![0; 64];
+            for (i, &natural) in ZIGZAG.iter().enumerate() {
+                values[natural] = match precision {
+                    8 => table[i] as u16,
+                    _ => u16::from_be_bytes([table[2 * i], table[2 * i + 1]]),
+                };
+            }
+            let (ijg_quality, ijg_exact) = ijg_match(&values, index);
+            self.quant_tables.retain(|t| t.index != index);
+            self.quant_tables.push(QuantTable {
+                index,
+                precision,
+                values,
+                ijg_quality,
+                ijg_exact,
+            });
+            payload = &rest[size..];
         }
+        Ok(())
     }
 }
 
-fn read_jpeg_marker(reader: &mut Bytes<BufReader<File>>) -> std::io::Result<u32> {
-    let mut b1 = 0;
-    let mut b2 = 0;
+/// Marker segment, payload excludes marker and length
+struct Segment<'a> {
+    marker: u8,
+    payload: &'a [u8],
+}
 
-    read_b1(reader, &mut b1)?;
-    read_b2(reader, &mut b1, &mut b2)
+/// Bounds-checked iterator over marker segments after SOI, up to EOI or end of data.
+/// Skips fill bytes, standalone markers and entropy coded data, truncated segment ends iteration
+struct Segments<'a> {
+    data: &'a [u8],
+    pos: usize,
+    done: bool,
+    /// EOI marker was reached
+    eoi: bool,
 }
 
-fn read_b1(reader: &mut Bytes<BufReader<File>>, b1: &mut u8) -> std::io::Result<()> {
-    *b1 = reader.next().unwrap()?;
-    while *b1 != 0xff {
-        *b1 = match reader.next() {
-            Some(val) => val?,
-            None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "")),
+impl<'a> Segments<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 2,
+            done: false,
+            eoi: false,
         }
     }
-    Ok(())
+
+    fn fail(&mut self, msg: String) -> Option<BResult<Segment<'a>>> {
+        self.done = true;
+        Some(Err(msg.into()))
+    }
 }
 
-fn read_b2(reader: &mut Bytes<BufReader<File>>, b1: &mut u8, b2: &mut u8) -> std::io::Result<u32> {
-    *b2 = reader.next().unwrap()?;
-    if *b2 == 0xff {
-        read_b2(reader, b1, b2)?;
+impl<'a> Iterator for Segments<'a> {
+    type Item = BResult<Segment<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data = self.data;
+        while !self.done {
+            // extraneous bytes before marker are skipped like libjpeg does
+            let Some(offset) = data.get(self.pos..)?.iter().position(|&b| b == 0xff) else {
+                self.done = true;
+                return None;
+            };
+            self.pos += offset + 1;
+            // fill bytes
+            while data.get(self.pos) == Some(&0xff) {
+                self.pos += 1;
+            }
+            let Some(&marker) = data.get(self.pos) else {
+                self.done = true;
+                return None;
+            };
+            self.pos += 1;
+            match marker {
+                0x00 | 0x01 | 0xd0..=0xd8 => continue,
+                0xd9 => {
+                    self.eoi = true;
+                    self.done = true;
+                    return None;
+                }
+                _ => (),
+            }
+            // truncated data ends like missing EOI
+            let Some(len) = data.get(self.pos..self.pos + 2) else {
+                self.done = true;
+                return None;
+            };
+            let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+            if len < 2 {
+                return self.fail(format!("Invalid length {} of marker {:02X}", len, marker));
+            }
+            let Some(payload) = data.get(self.pos + 2..self.pos + len) else {
+                self.done = true;
+                return None;
+            };
+            self.pos += len;
+            if marker == 0xda {
+                self.pos = skip_entropy_data(data, self.pos);
+            }
+            return Some(Ok(Segment { marker, payload }));
+        }
+        None
     }
-    if *b2 == 0x00 {
-        read_b1(reader, b1)?;
+}
+
+const GHOST_MIN_QUALITY: u8 = 20;
+const GHOST_STEP: u8 = 2;
+/// primary quality must be lower than current quality by more than this
+const GHOST_MARGIN: u8 = 10;
+/// error after the minimum must rise by this factor
+const GHOST_RISE: f64 = 1.1;
+
+/// Mean squared error between image and its jpeg compression at quality
+fn recompression_error(img: &image::GrayImage, quality: u8) -> BResult<f64> {
+    let mut data = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut data, quality).encode_image(img)?;
+    let recompressed =
+        image::load_from_memory_with_format(&data, image::ImageFormat::Jpeg)?.into_luma8();
+    let sum: f64 = img
+        .as_raw()
+        .iter()
+        .zip(recompressed.as_raw())
+        .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+        .sum();
+    Ok(sum / img.as_raw().len() as f64)
+}
+
+/// Offset of the first marker after entropy coded data
+fn skip_entropy_data(data: &[u8], mut pos: usize) -> usize {
+    while pos + 1 < data.len() {
+        if data[pos] == 0xff && !matches!(data[pos + 1], 0x00 | 0xd0..=0xd7 | 0xff) {
+            return pos;
+        }
+        pos += 1;
+    }
+    data.len()
+}
+
+/// IJG scaled standard table for quality 1-100, limited to baseline range
+fn ijg_table(standard: &[u16; 64], quality: u8) -> [u16; 64] {
+    let quality = quality as u32;
+    let scale = if quality < 50 {
+        5000 / quality
+    } else {
+        200 - quality * 2
+    };
+    standard.map(|v| ((v as u32 * scale + 50) / 100).clamp(1, 255) as u16)
+}
+
+fn ijg_standard(index: u8) -> &'static [u16; 64] {
+    if index == 0 {
+        &STD_LUMINANCE
+    } else {
+        &STD_CHROMINANCE
+    }
+}
+
+/// Quality whose scaled IJG standard table is closest to the values, and exact match flag
+fn ijg_match(values: &[u16], index: u8) -> (u8, bool) {
+    table_match(values, ijg_standard(index))
+}
+
+/// Quality whose IJG scaling of the standard table is closest to the values
+fn table_match(values: &[u16], standard: &[u16; 64]) -> (u8, bool) {
+    (1..=100)
+        .map(|q| {
+            let diff: u32 = ijg_table(standard, q)
+                .iter()
+                .zip(values)
+                .map(|(a, b)| a.abs_diff(*b) as u32)
+                .sum();
+            (q, diff)
+        })
+        .min_by_key(|&(q, diff)| (diff, std::cmp::Reverse(q)))
+        .map(|(q, diff)| (q, diff == 0))
+        .unwrap()
+}
+
+fn subsampling(components: &[Component]) -> String {
+    match components {
+        [_] => "gray".to_string(),
+        [y, c @ ..] if c.iter().all(|c| c.h_sampling == 1 && c.v_sampling == 1) => {
+            match (y.h_sampling, y.v_sampling) {
+                (1, 1) => "4:4:4".to_string(),
+                (2, 1) => "4:2:2".to_string(),
+                (2, 2) => "4:2:0".to_string(),
+                (1, 2) => "4:4:0".to_string(),
+                (4, 1) => "4:1:1".to_string(),
+                (h, v) => format!("{}x{}", h, v),
+            }
+        }
+        [y, ..] => format!("{}x{}", y.h_sampling, y.v_sampling),
+        [] => String::new(),
+    }
+}
+
+/// Same estimate as `jpeg_quality` from parsed tables
+fn estimated_quality(tables: &[QuantTable]) -> f32 {
+    let mut quality_avg = [0.0; 3];
+    for t in tables.iter().filter(|t| t.index < 3) {
+        // average of AC coefficients
+        let total: u32 = t.values[1..].iter().map(|&v| v as u32).sum();
+        quality_avg[t.index as usize] = 100.0 - total as f32 / 63.0;
+        for i in (t.index + 1)..3 {
+            quality_avg[i as usize] = quality_avg[t.index as usize];
+        }
+        if t.index > 0 {
+            let diff = (quality_avg[0] - quality_avg[1]).abs() * 0.49
+                + (quality_avg[0] - quality_avg[2]).abs() * 0.49;
+            return (quality_avg[0] + quality_avg[1] + quality_avg[2]) / 3.0 + diff;
+        }
+    }
+    quality_avg[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_jpeg(quality: u8) -> Vec<u8> {
+        let img = image::RgbImage::from_fn(64, 48, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8])
+        });
+        let mut data = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut data, quality)
+            .encode_image(&img)
+            .unwrap();
+        data
+    }
+
+    /// (offset, marker, total length) of segments before the first scan of a valid jpeg
+    fn header_segments(data: &[u8]) -> Vec<(usize, u8, usize)> {
+        let mut segments = Vec::new();
+        let mut pos = 2;
+        while data[pos + 1] != 0xda {
+            let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+            segments.push((pos, data[pos + 1], len + 2));
+            pos += len + 2;
+        }
+        segments
+    }
+
+    /// Rebuild jpeg with each DQT table in its own segment, as returned by `table`
+    fn rewrite_dqt(data: &[u8], table: impl Fn(u8, &[u8]) -> Vec<u8>) -> Vec<u8> {
+        let mut out = data[..2].to_vec();
+        let mut end = 2;
+        for (pos, marker, len) in header_segments(data) {
+            end = pos + len;
+            if marker != 0xdb {
+                out.extend_from_slice(&data[pos..end]);
+                continue;
+            }
+            let mut payload = &data[pos + 4..end];
+            while let Some((&pq_tq, rest)) = payload.split_first() {
+                let size = if pq_tq >> 4 == 0 { 64 } else { 128 };
+                let body = table(pq_tq, &rest[..size]);
+                out.extend_from_slice(&[0xff, 0xdb]);
+                out.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+                out.extend_from_slice(&body);
+                payload = &rest[size..];
+            }
+        }
+        out.extend_from_slice(&data[end..]);
+        out
+    }
+
+    #[test]
+    fn ijg_quality_of_standard_tables() {
+        for q in [10, 25, 50, 75, 90, 100] {
+            let info = JpegInfo::parse(&sample_jpeg(q)).unwrap();
+            assert_eq!(info.ijg_quality, Some(q));
+            assert!(info.quant_tables.iter().all(|t| t.ijg_exact));
+            assert_eq!(info.encoder, Encoder::Libjpeg);
+            assert_eq!(info.subsampling, "4:4:4");
+            assert_eq!((info.width, info.height), (64, 48));
+            assert!(info.eoi);
+        }
+    }
+
+    #[test]
+    fn recompression_ghost() {
+        let img = image::RgbImage::from_fn(256, 256, |x, y| {
+            let v = ((x as f32 / 5.0).sin() * 40.0 + (y as f32 / 23.0).cos() * 70.0 + 128.0) as u8;
+            image::Rgb([v, v / 2 + (y / 5) as u8, 200 - v / 3])
+        });
+        let encode = |img: &image::RgbImage, quality| {
+            let mut data = Vec::new();
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut data, quality)
+                .encode_image(img)
+                .unwrap();
+            data
+        };
+        let single = encode(&img, 90);
+        let mut info = JpegInfo::parse(&single).unwrap();
+        info.detect_recompression(&single).unwrap();
+        assert_eq!(info.primary_quality, None);
+
+        let first = image::load_from_memory(&encode(&img, 50))
+            .unwrap()
+            .into_rgb8();
+        let double = encode(&first, 90);
+        let mut info = JpegInfo::parse(&double).unwrap();
+        info.detect_recompression(&double).unwrap();
+        assert!(info.primary_quality.is_some_and(|q| q.abs_diff(50) <= 4));
+    }
+
+    #[test]
+    fn separate_and_16_bit_tables() {
+        let data = sample_jpeg(80);
+        let expected = JpegInfo::parse(&data).unwrap();
+
+        let separate = rewrite_dqt(&data, |pq_tq, table| [&[pq_tq], table].concat());
+        let info = JpegInfo::parse(&separate).unwrap();
+        assert_eq!(info.quant_tables.len(), expected.quant_tables.len());
+        assert_eq!(info.estimated_quality, expected.estimated_quality);
+
+        let wide = rewrite_dqt(&data, |pq_tq, table| {
+            let mut body = vec![0x10 | (pq_tq & 0x0f)];
+            table
+                .iter()
+                .for_each(|&v| body.extend_from_slice(&(v as u16).to_be_bytes()));
+            body
+        });
+        let info = JpegInfo::parse(&wide).unwrap();
+        assert!(info.quant_tables.iter().all(|t| t.precision == 16));
+        for (a, b) in info.quant_tables.iter().zip(&expected.quant_tables) {
+            assert_eq!(a.values, b.values);
+        }
+        assert_eq!(info.ijg_quality, Some(80));
+    }
+
+    #[test]
+    fn fill_bytes_and_missing_eoi() {
+        let data = sample_jpeg(60);
+        let expected = JpegInfo::parse(&data).unwrap();
+
+        let mut filled = data[..2].to_vec();
+        let mut end = 2;
+        for (pos, _, len) in header_segments(&data) {
+            filled.extend_from_slice(&[0xff, 0xff, 0xff]);
+            filled.extend_from_slice(&data[pos..pos + len]);
+            end = pos + len;
+        }
+        filled.extend_from_slice(&data[end..]);
+        let info = JpegInfo::parse(&filled).unwrap();
+        assert_eq!(info.ijg_quality, expected.ijg_quality);
+        assert!(info.eoi);
+
+        let info = JpegInfo::parse(&data[..data.len() - 2]).unwrap();
+        assert_eq!(info.ijg_quality, expected.ijg_quality);
+        assert!(!info.eoi);
+    }
+
+    #[test]
+    fn truncated_inputs() {
+        let data = sample_jpeg(75);
+        // end of frame header and quantization tables
+        let required = header_segments(&data)
+            .iter()
+            .filter(|s| s.1 == 0xc0 || s.1 == 0xdb)
+            .map(|s| s.0 + s.2)
+            .max()
+            .unwrap();
+        for len in 0..data.len() {
+            let result = JpegInfo::parse(&data[..len]);
+            assert_eq!(result.is_ok(), len >= required, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn corrupted_inputs_dont_panic() {
+        let data = sample_jpeg(75);
+        let header_len = header_segments(&data).last().map(|s| s.0 + s.2).unwrap();
+        // xorshift, deterministic
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..5000 {
+            let mut corrupted = data.clone();
+            for _ in 0..1 + next() % 8 {
+                let pos = (next() % header_len as u64) as usize;
+                corrupted[pos] = match next() % 4 {
+                    0 => 0xff,
+                    1 => 0x00,
+                    _ => next() as u8,
+                };
+            }
+            let cut = corrupted.len() - (next() % 64) as usize;
+            let _ = JpegInfo::parse(&corrupted[..cut]);
+        }
     }
-    Ok(*b1 as u32 * 256 + *b2 as u32)
 }
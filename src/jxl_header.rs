@@ -0,0 +1,294 @@
+//! Minimal JPEG XL header parser: image size and the image metadata fields
+//! needed to tell lossy from lossless and still from animated images.
+
+use std::{
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{metadata::isobmff_boxes, BResult};
+
+const CODESTREAM_SIGNATURE: &[u8] = &[0xff, 0x0a];
+const CONTAINER_SIGNATURE: &[u8] = b"\0\0\0\x0cJXL \r\n\x87\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JxlHeader {
+    pub width: u32,
+    pub height: u32,
+    pub animated: bool,
+    /// colors are stored in XYB, which is used by lossy (VarDCT) encoding
+    pub xyb_encoded: bool,
+    /// container has jpeg reconstruction data
+    pub jpeg_reconstruction: bool,
+}
+
+impl JxlHeader {
+    /// Pixels were lossy encoded, either by jxl itself or by a transcoded jpeg
+    pub fn is_lossy(&self) -> bool {
+        self.xyb_encoded || self.jpeg_reconstruction
+    }
+}
+
+/// Parse header of bare codestream or container
+pub fn read(data: &[u8]) -> BResult<JxlHeader> {
+    let (codestream, jpeg_reconstruction) = if data.starts_with(CONTAINER_SIGNATURE) {
+        let boxes = isobmff_boxes(data);
+        let codestream = boxes
+            .iter()
+            .find_map(|(typ, payload)| match typ {
+                b"jxlc" => Some(*payload),
+                // partial codestream starts with 4 bytes index
+                b"jxlp" => payload.get(4..),
+                _ => None,
+            })
+            .ok_or("No codestream in jxl container")?;
+        let jbrd = boxes.iter().any(|(typ, _)| typ == b"jbrd");
+        (codestream, jbrd)
+    } else {
+        (data, false)
+    };
+    read_codestream(codestream, jpeg_reconstruction)
+}
+
+/// Parse header from the start of the file, container boxes before codestream are skipped
+pub fn read_file(path: &Path) -> BResult<JxlHeader> {
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    let mut signature = [0u8; CONTAINER_SIGNATURE.len()];
+    reader.read_exact(&mut signature[..CODESTREAM_SIGNATURE.len()])?;
+    if signature.starts_with(CODESTREAM_SIGNATURE) {
+        reader.seek(SeekFrom::Start(0))?;
+        return read_codestream(&read_prefix(&mut reader)?, false);
+    }
+    reader.read_exact(&mut signature[CODESTREAM_SIGNATURE.len()..])?;
+    if signature != CONTAINER_SIGNATURE {
+        return Err("Not a jxl file".into());
+    }
+    let mut jpeg_reconstruction = false;
+    loop {
+        let mut header = [0u8; 8];
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| "No codestream in jxl container")?;
+        let size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64;
+        let payload = match size {
+            // box extends to the end of file
+            0 => u64::MAX,
+            1 => {
+                let mut large = [0u8; 8];
+                reader.read_exact(&mut large)?;
+                u64::from_be_bytes(large)
+                    .checked_sub(16)
+                    .ok_or("Invalid jxl box size")?
+            }
+            s => s.checked_sub(8).ok_or("Invalid jxl box size")?,
+        };
+        match &header[4..8] {
+            b"jxlc" => return read_codestream(&read_prefix(&mut reader)?, jpeg_reconstruction),
+            b"jxlp" => {
+                // partial codestream starts with 4 bytes index
+                reader.seek(SeekFrom::Current(4))?;
+                return read_codestream(&read_prefix(&mut reader)?, jpeg_reconstruction);
+            }
+            b"jbrd" => jpeg_reconstruction = true,
+            _ => (),
+        }
+        if size == 0 {
+            return Err("No codestream in jxl container".into());
+        }
+        reader.seek(SeekFrom::Current(
+            i64::try_from(payload).map_err(|_| "Invalid jxl box size")?,
+        ))?;
+    }
+}
+
+/// Start of codestream, which is enough for the header
+fn read_prefix(reader: &mut impl Read) -> BResult<Vec<u8>> {
+    const HEADER_PREFIX: u64 = 4096;
+    let mut prefix = Vec::new();
+    reader.take(HEADER_PREFIX).read_to_end(&mut prefix)?;
+    Ok(prefix)
+}
+
+fn read_codestream(codestream: &[u8], jpeg_reconstruction: bool) -> BResult<JxlHeader> {
+    if !codestream.starts_with(CODESTREAM_SIGNATURE) {
+        return Err("Not a jxl codestream".into());
+    }
+    let mut r = BitReader::new(&codestream[2..]);
+    let (width, height) = read_size_header(&mut r)?;
+    let (animated, xyb_encoded) = read_image_metadata(&mut r)?;
+    Ok(JxlHeader {
+        width,
+        height,
+        animated,
+        xyb_encoded,
+        jpeg_reconstruction,
+    })
+}
+
+/// Distribution of U32 field: constant or offset + bits
+#[derive(Clone, Copy)]
+enum Dist {
+    Val(u32),
+    Bits(u32, u32),
+}
+use Dist::{Bits, Val};
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Read n <= 32 bits, least significant first
+    fn bits(&mut self, n: u32) -> BResult<u32> {
+        let mut v = 0u64;
+        for i in 0..n {
+            let byte = self
+                .data
+                .get(self.pos / 8)
+                .ok_or("Unexpected end of jxl header")?;
+            v |= (((byte >> (self.pos % 8)) & 1) as u64) << i;
+            self.pos += 1;
+        }
+        Ok(v as u32)
+    }
+
+    fn skip(&mut self, n: u32) -> BResult<()> {
+        self.pos += n as usize;
+        if self.pos > self.data.len() * 8 {
+            return Err("Unexpected end of jxl header".into());
+        }
+        Ok(())
+    }
+
+    fn bool(&mut self) -> BResult<bool> {
+        Ok(self.bits(1)? == 1)
+    }
+
+    fn u32(&mut self, dists: [Dist; 4]) -> BResult<u32> {
+        match dists[self.bits(2)? as usize] {
+            Val(v) => Ok(v),
+            Bits(n, offset) => Ok(self.bits(n)?.wrapping_add(offset)),
+        }
+    }
+}
+
+const SIZE_DISTS: [Dist; 4] = [Bits(9, 1), Bits(13, 1), Bits(18, 1), Bits(30, 1)];
+
+fn ratio_width(ratio: u32, height: u32) -> u32 {
+    let h = height as u64;
+    let w = match ratio {
+        1 => h,
+        2 => h * 12 / 10,
+        3 => h * 4 / 3,
+        4 => h * 3 / 2,
+        5 => h * 16 / 9,
+        6 => h * 5 / 4,
+        _ => h * 2,
+    };
+    w as u32
+}
+
+fn read_size_header(r: &mut BitReader) -> BResult<(u32, u32)> {
+    let small = r.bool()?;
+    let height = if small {
+        (r.bits(5)? + 1) * 8
+    } else {
+        r.u32(SIZE_DISTS)?
+    };
+    let ratio = r.bits(3)?;
+    let width = match (ratio, small) {
+        (0, true) => (r.bits(5)? + 1) * 8,
+        (0, false) => r.u32(SIZE_DISTS)?,
+        (ratio, _) => ratio_width(ratio, height),
+    };
+    Ok((width, height))
+}
+
+fn read_preview_header(r: &mut BitReader) -> BResult<()> {
+    let div8 = r.bool()?;
+    let dists = if div8 {
+        [Val(16), Val(32), Bits(5, 1), Bits(9, 33)]
+    } else {
+        [Bits(6, 1), Bits(8, 65), Bits(10, 321), Bits(12, 1345)]
+    };
+    r.u32(dists)?;
+    if r.bits(3)? == 0 {
+        r.u32(dists)?;
+    }
+    Ok(())
+}
+
+fn read_bit_depth(r: &mut BitReader) -> BResult<()> {
+    if r.bool()? {
+        r.u32([Val(32), Val(16), Val(24), Bits(6, 1)])?;
+        r.bits(4)?;
+    } else {
+        r.u32([Val(8), Val(10), Val(12), Bits(6, 1)])?;
+    }
+    Ok(())
+}
+
+fn read_extra_channel_info(r: &mut BitReader) -> BResult<()> {
+    if r.bool()? {
+        return Ok(());
+    }
+    let typ = r.u32([Val(0), Val(1), Bits(4, 2), Bits(6, 18)])?;
+    read_bit_depth(r)?;
+    r.u32([Val(0), Val(3), Val(4), Bits(3, 1)])?;
+    let name_len = r.u32([Val(0), Bits(4, 0), Bits(5, 16), Bits(10, 48)])?;
+    r.skip(8 * name_len)?;
+    match typ {
+        // alpha: alpha_associated
+        0 => {
+            r.bool()?;
+        }
+        // spot color: 4 f16 values
+        2 => r.skip(64)?,
+        // color filter array: cfa channel
+        5 => {
+            r.u32([Val(1), Bits(2, 0), Bits(4, 3), Bits(8, 19)])?;
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Return (animated, xyb_encoded) from ImageMetadata
+fn read_image_metadata(r: &mut BitReader) -> BResult<(bool, bool)> {
+    if r.bool()? {
+        // all default
+        return Ok((false, true));
+    }
+    let mut animated = false;
+    if r.bool()? {
+        // extra fields: orientation, intrinsic size, preview, animation
+        r.bits(3)?;
+        if r.bool()? {
+            read_size_header(r)?;
+        }
+        if r.bool()? {
+            read_preview_header(r)?;
+        }
+        animated = r.bool()?;
+        if animated {
+            r.u32([Val(100), Val(1000), Bits(10, 1), Bits(30, 1)])?;
+            r.u32([Val(1), Val(1001), Bits(8, 1), Bits(10, 1)])?;
+            r.u32([Val(0), Bits(3, 0), Bits(16, 0), Bits(32, 0)])?;
+            r.bool()?;
+        }
+    }
+    read_bit_depth(r)?;
+    // modular 16 bit buffers
+    r.bool()?;
+    let num_extra = r.u32([Val(0), Val(1), Bits(4, 2), Bits(12, 1)])?;
+    for _ in 0..num_extra {
+        read_extra_channel_info(r)?;
+    }
+    let xyb_encoded = r.bool()?;
+    Ok((animated, xyb_encoded))
+}
@@ -1,8 +1,12 @@
 use std::error::Error;
 
 pub mod find {
+    pub mod action;
     pub mod bpp;
+    pub mod corrupt;
     pub mod detailed;
+    pub mod jpeg_quality;
+    pub mod mismatched_ext;
     pub mod monochrome;
     pub mod resizable;
     pub mod similar;
@@ -11,9 +15,16 @@ pub mod cmds;
 pub mod convert;
 pub mod csv_output;
 pub mod gen;
+pub mod is_animated;
 pub mod is_apng;
+pub mod journal;
 pub mod jpegquality;
+pub mod jxl_header;
+pub mod metadata;
+pub mod png_chunks;
+pub mod sniff;
 pub mod utils;
+pub mod walk;
 
 pub mod args;
 
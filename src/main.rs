@@ -5,6 +5,7 @@ use std::{env, fs, io};
 
 fn main() -> BResult<()> {
     let opt = args::Opt::parse();
+    journal::init(opt.journal.as_deref(), opt.dry_run, opt.on_conflict);
 
     match opt.subcommand {
         args::Commands::Find { subcommand } => match subcommand {
@@ -13,16 +14,24 @@ fn main() -> BResult<()> {
             args::SelectableFind::Resizable(opt) => find::resizable::main(opt)?,
             args::SelectableFind::Similar(opt) => find::similar::main(opt)?,
             args::SelectableFind::Detailed(opt) => find::detailed::main(opt)?,
+            args::SelectableFind::JpegQuality(opt) => find::jpeg_quality::main(opt)?,
+            args::SelectableFind::MismatchedExt(opt) => find::mismatched_ext::main(opt)?,
+            args::SelectableFind::Corrupt(opt) => find::corrupt::main(opt)?,
         },
         args::Commands::Gen { subcommand } => match subcommand {
+            args::SelectableGen::Apng2frames(opt) => gen::apng2frames::main(opt)?,
             args::SelectableGen::FfmpegConcat(opt) => gen::ffmpeg_concat::main(opt)?,
             args::SelectableGen::Video(opt) => gen::video::main(opt)?,
             args::SelectableGen::Zip2video(opt) => gen::zip2video::main(opt)?,
         },
         args::Commands::Cmds(opt) => cmds::main(opt)?,
         args::Commands::Convert(opt) => convert::main(opt)?,
+        args::Commands::IsAnimated(opt) => is_animated::main(opt)?,
         args::Commands::IsApng(opt) => is_apng::main(opt)?,
+        args::Commands::JpegInfo(opt) => jpegquality::main(opt)?,
+        args::Commands::Png(opt) => png_chunks::main(opt)?,
         args::Commands::ShellCompletions => gen_shell_completions()?,
+        args::Commands::Undo(opt) => journal::main(opt)?,
     }
     Ok(())
 }
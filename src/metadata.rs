@@ -0,0 +1,355 @@
+use std::path::Path;
+
+use clap::ValueEnum;
+
+use crate::{utils, BResult};
+
+const PNG_HEADER: &[u8] = b"\x89PNG\r\n\x1a\n";
+const JXL_CONTAINER: &[u8] = b"\0\0\0\x0cJXL \r\n\x87\n";
+const XMP_JPEG_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// What to do with source metadata in saved results
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetadataPolicy {
+    /// keep exif, xmp and icc profile
+    #[default]
+    Keep,
+    /// remove all metadata
+    Strip,
+    /// keep only icc profile
+    KeepIcc,
+}
+
+/// Image metadata payloads.
+/// Payload can be empty if the data is stored outside of the parsed boxes (avif items)
+#[derive(Debug, Default, Clone)]
+pub struct Metadata {
+    pub exif: Option<Vec<u8>>,
+    pub xmp: Option<Vec<u8>>,
+    pub icc: Option<Vec<u8>>,
+    /// icc profile is stored in jxl codestream and can't be checked
+    icc_unknown: bool,
+}
+
+impl Metadata {
+    /// Read metadata from jpeg, png, webp, jxl container or avif,
+    /// other formats are treated as having no metadata
+    pub fn read(path: &Path) -> BResult<Self> {
+        let data = std::fs::read(path)?;
+        let mut m = Self::default();
+        if data.starts_with(&[0xff, 0xd8]) {
+            m.read_jpeg(&data);
+        } else if data.starts_with(PNG_HEADER) {
+            m.read_png(&data);
+        } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+            m.read_webp(&data);
+        } else if data.starts_with(JXL_CONTAINER) {
+            m.read_jxl(&data);
+        } else if data.starts_with(&[0xff, 0x0a]) {
+            m.icc_unknown = true;
+        } else if data.get(4..8) == Some(b"ftyp") {
+            m.read_isobmff(&data);
+        }
+        Ok(m)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exif.is_none() && self.xmp.is_none() && self.icc.is_none()
+    }
+
+    fn read_jpeg(&mut self, data: &[u8]) {
+        let mut pos = 2;
+        while pos + 4 <= data.len() {
+            if data[pos] != 0xff {
+                pos += 1;
+                continue;
+            }
+            let marker = data[pos + 1];
+            match marker {
+                0xff => {
+                    pos += 1;
+                    continue;
+                }
+                0x01 | 0xd0..=0xd7 => {
+                    pos += 2;
+                    continue;
+                }
+                0xd9 | 0xda => return,
+                _ => (),
+            }
+            let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+            let Some(payload) = data.get(pos + 4..pos + 2 + len.max(2)) else {
+                return;
+            };
+            match marker {
+                0xe1 if payload.starts_with(b"Exif\0\0") => {
+                    self.exif = Some(payload[6..].to_vec());
+                }
+                0xe1 if payload.starts_with(XMP_JPEG_HEADER) => {
+                    self.xmp = Some(payload[XMP_JPEG_HEADER.len()..].to_vec());
+                }
+                // ICC_PROFILE\0, sequence number, number of chunks
+                0xe2 if payload.starts_with(b"ICC_PROFILE\0") && payload.len() >= 14 => {
+                    self.icc
+                        .get_or_insert_with(Vec::new)
+                        .extend_from_slice(&payload[14..]);
+                }
+                _ => (),
+            }
+            pos += 2 + len;
+        }
+    }
+
+    fn read_png(&mut self, data: &[u8]) {
+        let mut pos = PNG_HEADER.len();
+        while pos + 8 <= data.len() {
+            let len = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+            let typ = &data[pos + 4..pos + 8];
+            let Some(payload) = data.get(pos + 8..pos + 8 + len) else {
+                return;
+            };
+            let keyword = payload.split(|&b| b == 0).next().unwrap_or_default();
+            match typ {
+                b"eXIf" => self.exif = Some(payload.to_vec()),
+                b"iCCP" => self.icc = Some(payload.to_vec()),
+                b"iTXt" if keyword == b"XML:com.adobe.xmp" => self.xmp = Some(payload.to_vec()),
+                b"tEXt" | b"zTXt" | b"iTXt" if keyword == b"Raw profile type exif" => {
+                    self.exif = Some(payload.to_vec())
+                }
+                b"tEXt" | b"zTXt" | b"iTXt" if keyword == b"Raw profile type xmp" => {
+                    self.xmp = Some(payload.to_vec())
+                }
+                b"IEND" => return,
+                _ => (),
+            }
+            pos += 12 + len;
+        }
+    }
+
+    fn read_webp(&mut self, data: &[u8]) {
+        let mut pos = 12;
+        while pos + 8 <= data.len() {
+            let len = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+            let Some(payload) = data.get(pos + 8..pos + 8 + len) else {
+                return;
+            };
+            match &data[pos..pos + 4] {
+                b"EXIF" => self.exif = Some(payload.to_vec()),
+                b"XMP " => self.xmp = Some(payload.to_vec()),
+                b"ICCP" => self.icc = Some(payload.to_vec()),
+                _ => (),
+            }
+            pos += 8 + len + len % 2;
+        }
+    }
+
+    fn read_jxl(&mut self, data: &[u8]) {
+        self.icc_unknown = true;
+        for (typ, payload) in isobmff_boxes(data) {
+            match &typ {
+                // 4 bytes tiff header offset
+                b"Exif" if payload.len() >= 4 => self.exif = Some(payload[4..].to_vec()),
+                b"xml " => self.xmp = Some(payload.to_vec()),
+                // brotli compressed box, payload starts with the inner box type
+                b"brob" => match payload.get(..4) {
+                    Some(b"Exif") => self.exif = Some(Vec::new()),
+                    Some(b"xml ") => self.xmp = Some(Vec::new()),
+                    _ => (),
+                },
+                _ => (),
+            }
+        }
+    }
+
+    fn read_isobmff(&mut self, data: &[u8]) {
+        let Some((_, meta)) = isobmff_boxes(data).into_iter().find(|b| &b.0 == b"meta") else {
+            return;
+        };
+        // skip full box version & flags
+        for (typ, payload) in isobmff_boxes(meta.get(4..).unwrap_or_default()) {
+            match &typ {
+                b"iinf" => self.read_isobmff_iinf(payload),
+                b"iprp" => {
+                    for (_, ipco) in isobmff_boxes(payload).iter().filter(|b| &b.0 == b"ipco") {
+                        for (_, colr) in isobmff_boxes(ipco).iter().filter(|b| &b.0 == b"colr") {
+                            if colr.starts_with(b"prof") || colr.starts_with(b"rICC") {
+                                self.icc = Some(colr[4..].to_vec());
+                            }
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn read_isobmff_iinf(&mut self, iinf: &[u8]) {
+        let entries_offset = match iinf.first() {
+            Some(0) => 6,
+            Some(_) => 8,
+            None => return,
+        };
+        for (typ, infe) in isobmff_boxes(iinf.get(entries_offset..).unwrap_or_default()) {
+            if &typ != b"infe" {
+                continue;
+            }
+            // version 2: u16 item id, version 3: u32 item id, then u16 protection index
+            let type_offset = match infe.first() {
+                Some(2) => 8,
+                Some(3) => 10,
+                _ => continue,
+            };
+            match infe.get(type_offset..type_offset + 4) {
+                Some(b"Exif") => self.exif = Some(Vec::new()),
+                Some(b"mime")
+                    if infe
+                        .windows(b"application/rdf+xml".len())
+                        .any(|w| w == b"application/rdf+xml") =>
+                {
+                    self.xmp = Some(Vec::new())
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Top level boxes of isobmff / jxl container data
+pub(crate) fn isobmff_boxes(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut boxes = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let size = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as u64;
+        let typ: [u8; 4] = data[pos + 4..pos + 8].try_into().unwrap();
+        let (header, size) = match size {
+            0 => (8, (data.len() - pos) as u64),
+            1 => match data.get(pos + 8..pos + 16) {
+                Some(s) => (16, u64::from_be_bytes(s.try_into().unwrap())),
+                None => break,
+            },
+            s => (8, s),
+        };
+        let end = match usize::try_from(size).ok().and_then(|s| pos.checked_add(s)) {
+            Some(e) if size >= header as u64 && e <= data.len() => e,
+            _ => break,
+        };
+        boxes.push((typ, &data[pos + header..end]));
+        pos = end;
+    }
+    boxes
+}
+
+/// Make output metadata follow the policy: reinject source metadata lost by encoder,
+/// or strip unwanted metadata with exiftool. Restore source modification time on output
+pub fn apply_policy(policy: MetadataPolicy, source: &Path, output: &Path) -> BResult<()> {
+    apply_policy_from(policy, &Metadata::read(source)?, source, output)
+}
+
+/// Same as `apply_policy` with already read source metadata,
+/// payloads removed from `src` are not reinjected
+pub fn apply_policy_from(
+    policy: MetadataPolicy,
+    src: &Metadata,
+    source: &Path,
+    output: &Path,
+) -> BResult<()> {
+    // mtime is restored even if metadata can't be fixed
+    let res = apply_metadata(policy, src, source, output);
+    restore_mtime(source, output)?;
+    res
+}
+
+fn apply_metadata(
+    policy: MetadataPolicy,
+    src: &Metadata,
+    source: &Path,
+    output: &Path,
+) -> BResult<()> {
+    let out = Metadata::read(output)?;
+
+    let mut status = Vec::new();
+    let mut inject_args = Vec::new();
+    let mut strip_args = Vec::new();
+    let kinds = [
+        (
+            "exif",
+            "-EXIF:all",
+            src.exif.is_some(),
+            out.exif.is_some(),
+            false,
+        ),
+        (
+            "xmp",
+            "-XMP:all",
+            src.xmp.is_some(),
+            out.xmp.is_some(),
+            false,
+        ),
+        (
+            "icc",
+            "-ICC_Profile",
+            src.icc.is_some(),
+            out.icc.is_some(),
+            true,
+        ),
+    ];
+    for (name, tag, in_src, in_out, is_icc) in kinds {
+        // jxl encoders keep color profile in codestream
+        if is_icc && out.icc_unknown {
+            continue;
+        }
+        let wanted = match policy {
+            MetadataPolicy::Keep => true,
+            MetadataPolicy::Strip => false,
+            MetadataPolicy::KeepIcc => is_icc,
+        };
+        match (wanted, in_src, in_out) {
+            (true, true, true) => status.push(format!("{} kept", name)),
+            (true, true, false) => inject_args.push((name, tag.to_string())),
+            (false, _, true) => {
+                strip_args.push(format!("{}=", tag));
+                status.push(format!("{} stripped", name));
+            }
+            _ => (),
+        }
+    }
+
+    if !inject_args.is_empty() {
+        let outp = std::process::Command::new("exiftool")
+            .args(["-q", "-overwrite_original_in_place", "-TagsFromFile"])
+            .arg(source)
+            .args(inject_args.iter().map(|a| &a.1))
+            .arg(output)
+            .output();
+        let injected = matches!(outp, Ok(ref o) if utils::command_print_if_error(o).is_ok());
+        for (name, _) in inject_args {
+            status.push(format!(
+                "{} {}",
+                name,
+                if injected { "reinjected" } else { "lost" }
+            ));
+        }
+    }
+    if !strip_args.is_empty() {
+        let outp = std::process::Command::new("exiftool")
+            .args(["-q", "-overwrite_original_in_place"])
+            .args(&strip_args)
+            .arg(output)
+            .output()?;
+        utils::command_print_if_error(&outp)?;
+    }
+    if !status.is_empty() {
+        println!("Metadata: {}", status.join(", "));
+    }
+    Ok(())
+}
+
+/// Set modification time of output to the one of source
+pub fn restore_mtime(source: &Path, output: &Path) -> BResult<()> {
+    let mtime = source.metadata()?.modified()?;
+    std::fs::File::options()
+        .write(true)
+        .open(output)?
+        .set_modified(mtime)?;
+    Ok(())
+}
@@ -0,0 +1,285 @@
+use std::{
+    io::{BufReader, ErrorKind, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::Args;
+use flate2::Crc;
+
+use crate::{
+    is_apng::{read_png_header, read_uint32, ChunkHeaderData},
+    journal::{self, Entry},
+    sniff::ImageFormat,
+    walk::WalkOpt,
+    BResult,
+};
+
+const PNG_HEADER: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[derive(Args, Debug, Clone)]
+pub struct Opt {
+    /// input png paths
+    #[arg(required = false, default_value = "./*", display_order = 0)]
+    input: Vec<PathBuf>,
+    #[command(flatten)]
+    walk: WalkOpt,
+    /// strip ancillary chunks without re-encoding
+    #[arg(short, long)]
+    strip: bool,
+    /// ancillary chunk types to strip
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "tEXt,zTXt,iTXt,tIME,eXIf"
+    )]
+    chunks: Vec<String>,
+    /// output directory for stripped pngs, default is to replace input keeping it as '{name}-bak.png'
+    #[arg(short)]
+    out_dir: Option<PathBuf>,
+    /// print only problems and stripped chunks
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+pub fn main(opt: Opt) -> BResult<()> {
+    let strip_types = opt
+        .chunks
+        .iter()
+        .map(|c| {
+            let typ: [u8; 4] = c
+                .as_bytes()
+                .try_into()
+                .map_err(|_| format!("Invalid chunk type: {}", c))?;
+            // critical chunks have uppercase first letter
+            if typ[0].is_ascii_uppercase() {
+                return Err(format!("Can't strip critical chunk: {}", c));
+            }
+            Ok(typ)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if let (Some(out_dir), false) = (&opt.out_dir, journal::dry_run()) {
+        journal::mkdir(out_dir)?;
+    }
+
+    let images = opt
+        .walk
+        .expand_formats(&opt.input, &[ImageFormat::Png, ImageFormat::Apng])?;
+    for path in &images {
+        let png = match PngChunks::read(path) {
+            Ok(png) => png,
+            Err(e) => {
+                eprintln!("Error reading png {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        println!("File: {}", path.display());
+        png.print(opt.quiet);
+        if opt.strip {
+            let output = match &opt.out_dir {
+                Some(dir) => dir.join(path.file_name().unwrap()),
+                None => path.clone(),
+            };
+            if let Err(e) = png.strip(path, &output, &strip_types) {
+                eprintln!("Error stripping png {}: {}", path.display(), e);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    /// position of chunk length in file
+    pub offset: u64,
+    pub typ: [u8; 4],
+    pub length: u32,
+    pub crc: u32,
+    pub crc_ok: bool,
+}
+
+impl Chunk {
+    pub fn type_str(&self) -> String {
+        String::from_utf8_lossy(&self.typ).into_owned()
+    }
+
+    /// Chunk data and crc size
+    fn size(&self) -> u64 {
+        12 + self.length as u64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PngChunks {
+    pub chunks: Vec<Chunk>,
+    pub iend: bool,
+    /// file ends in the middle of a chunk
+    pub truncated: bool,
+    /// bytes after `IEND`
+    pub trailing: u64,
+}
+
+impl PngChunks {
+    /// Walk all chunks and verify their crc
+    pub fn read(path: &Path) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        read_png_header(&mut reader)?;
+
+        let mut offset = PNG_HEADER.len() as u64;
+        let mut chunks = Vec::new();
+        let mut iend = false;
+        let mut truncated = false;
+        while offset < file_len {
+            match read_chunk(&mut reader, offset) {
+                Ok(chunk) => {
+                    offset += chunk.size();
+                    iend = &chunk.typ == b"IEND";
+                    chunks.push(chunk);
+                    if iend {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                    truncated = true;
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Self {
+            chunks,
+            iend,
+            truncated,
+            trailing: if truncated {
+                0
+            } else {
+                file_len.saturating_sub(offset)
+            },
+        })
+    }
+
+    /// All chunks have valid crc and end with `IEND`
+    pub fn is_valid(&self) -> bool {
+        self.iend && !self.truncated && self.chunks.iter().all(|c| c.crc_ok)
+    }
+
+    fn print(&self, quiet: bool) {
+        for c in &self.chunks {
+            if !quiet || !c.crc_ok {
+                println!(
+                    " {:>10} {} {:>10} {:08x}{}",
+                    c.offset,
+                    c.type_str(),
+                    c.length,
+                    c.crc,
+                    if c.crc_ok { "" } else { " crc mismatch" }
+                );
+            }
+        }
+        if self.truncated {
+            println!(" truncated chunk");
+        } else if !self.iend {
+            println!(" missing IEND");
+        }
+        if self.trailing > 0 {
+            println!(" trailing data: {} bytes", self.trailing);
+        }
+    }
+
+    /// Copy png without chunks of given types, trailing data is dropped
+    pub fn strip(&self, input: &Path, output: &Path, types: &[[u8; 4]]) -> BResult<()> {
+        if !self.is_valid() {
+            return Err("Not stripping invalid png".into());
+        }
+        let stripped: Vec<&Chunk> = self
+            .chunks
+            .iter()
+            .filter(|c| types.contains(&c.typ))
+            .collect();
+        if stripped.is_empty() && self.trailing == 0 && input == output {
+            return Ok(());
+        }
+        // replaced file is kept like in `convert`
+        let backup = output.with_file_name(format!(
+            "{}-bak.png",
+            output.file_stem().unwrap_or_default().to_string_lossy()
+        ));
+        if journal::dry_run() {
+            if output.exists() {
+                journal::place(output, &backup, |backup| {
+                    println!("{} --> {}", output.display(), backup.display());
+                    Ok(())
+                })?;
+            }
+            println!("write {}", output.display());
+        } else {
+            self.write_stripped(input, output, types, &backup)?;
+        }
+
+        for c in stripped {
+            println!(" stripped {} ({} bytes)", c.type_str(), c.length);
+        }
+        if self.trailing > 0 {
+            println!(" stripped trailing data ({} bytes)", self.trailing);
+        }
+        Ok(())
+    }
+
+    fn write_stripped(
+        &self,
+        input: &Path,
+        output: &Path,
+        types: &[[u8; 4]],
+        backup: &Path,
+    ) -> BResult<()> {
+        let mut reader = BufReader::new(std::fs::File::open(input)?);
+        let dir = output.parent().filter(|p| !p.as_os_str().is_empty());
+        let mut tmp = tempfile::NamedTempFile::new_in(dir.unwrap_or(Path::new(".")))?;
+        let mut header = vec![0u8; PNG_HEADER.len()];
+        reader.read_exact(&mut header)?;
+        tmp.write_all(&header)?;
+        for c in &self.chunks {
+            if types.contains(&c.typ) {
+                reader.seek_relative(c.size() as i64)?;
+            } else {
+                std::io::copy(&mut (&mut reader).take(c.size()), &mut tmp)?;
+            }
+        }
+        std::fs::set_permissions(tmp.path(), std::fs::metadata(input)?.permissions())?;
+        tmp.as_file().sync_all()?;
+        if output.exists() {
+            journal::backup(output, backup)?;
+        }
+        tmp.persist(output)?;
+        journal::record(Entry::Create {
+            path: output.to_path_buf(),
+        })
+    }
+}
+
+fn read_chunk(reader: &mut (impl Read + Seek), offset: u64) -> std::io::Result<Chunk> {
+    let chd = ChunkHeaderData::new(reader)?;
+    let mut crc = Crc::new();
+    crc.update(&chd.typ);
+    let mut data = reader.by_ref().take(chd.length as u64);
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = data.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        crc.update(&buf[..n]);
+    }
+    if data.limit() > 0 {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    let stored = read_uint32(reader)?;
+    Ok(Chunk {
+        offset,
+        typ: chd.typ,
+        length: chd.length,
+        crc: stored,
+        crc_ok: crc.sum() == stored,
+    })
+}
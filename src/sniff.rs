@@ -0,0 +1,116 @@
+use std::{
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::is_apng;
+
+/// Image format detected from file content
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Png,
+    /// png with more than one frame
+    Apng,
+    Jpeg,
+    Webp,
+    Gif,
+    Bmp,
+    /// jxl codestream or container
+    Jxl,
+    Avif,
+    Heif,
+}
+
+impl ImageFormat {
+    /// Detect format from file header, apng needs the whole reader
+    pub fn sniff(path: &Path) -> std::io::Result<Option<Self>> {
+        let mut reader = BufReader::new(std::fs::File::open(path)?);
+        Self::sniff_reader(&mut reader)
+    }
+
+    pub fn sniff_reader(reader: &mut (impl Read + Seek)) -> std::io::Result<Option<Self>> {
+        let mut magic = [0u8; 64];
+        let n = reader.by_ref().take(64).read(&mut magic)?;
+        reader.seek(SeekFrom::Start(0))?;
+        Ok(match Self::from_magic(&magic[..n]) {
+            Some(Self::Png) if is_apng::decode(reader).is_ok_and(|n| n >= 2) => Some(Self::Apng),
+            format => format,
+        })
+    }
+
+    /// Detect format from first bytes, apng is reported as png
+    pub fn from_magic(magic: &[u8]) -> Option<Self> {
+        if magic.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Self::Png)
+        } else if magic.starts_with(b"\xff\xd8\xff") {
+            Some(Self::Jpeg)
+        } else if magic.starts_with(b"RIFF") && magic.get(8..12) == Some(b"WEBP") {
+            Some(Self::Webp)
+        } else if magic.starts_with(b"GIF87a") || magic.starts_with(b"GIF89a") {
+            Some(Self::Gif)
+        } else if magic.starts_with(b"\xff\x0a") || magic.starts_with(b"\0\0\0\x0cJXL \r\n\x87\n") {
+            Some(Self::Jxl)
+        } else if magic.get(4..8) == Some(b"ftyp") {
+            Self::from_brands(magic)
+        } else if magic.starts_with(b"BM") {
+            // dib header size of known bmp versions
+            let dib = magic.get(14..18)?;
+            matches!(
+                u32::from_le_bytes(dib.try_into().ok()?),
+                12 | 40 | 52 | 56 | 64 | 108 | 124
+            )
+            .then_some(Self::Bmp)
+        } else {
+            None
+        }
+    }
+
+    /// Major and compatible brands of `ftyp` box
+    fn from_brands(magic: &[u8]) -> Option<Self> {
+        let size = u32::from_be_bytes(magic.get(0..4)?.try_into().ok()?) as usize;
+        let ftyp = magic.get(8..size.min(magic.len()))?;
+        // minor version isn't a brand
+        let brands = ftyp
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(i, _)| *i != 1)
+            .map(|(_, b)| b);
+        let mut heif = false;
+        for brand in brands {
+            match brand {
+                b"avif" | b"avis" => return Some(Self::Avif),
+                b"heic" | b"heix" | b"heim" | b"heis" | b"hevc" | b"hevx" | b"mif1" | b"msf1" => {
+                    heif = true
+                }
+                _ => (),
+            }
+        }
+        heif.then_some(Self::Heif)
+    }
+
+    /// Known extensions, first is canonical
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            Self::Png => &["png"],
+            Self::Apng => &["png", "apng"],
+            Self::Jpeg => &["jpg", "jpeg", "jpe", "jfif"],
+            Self::Webp => &["webp"],
+            Self::Gif => &["gif"],
+            Self::Bmp => &["bmp", "dib"],
+            Self::Jxl => &["jxl"],
+            Self::Avif => &["avif"],
+            Self::Heif => &["heic", "heif", "hif"],
+        }
+    }
+
+    /// File extension is one of known for the format, ignoring case
+    pub fn matches_extension(&self, path: &Path) -> bool {
+        let ext = path.extension().unwrap_or_default().to_string_lossy();
+        self.extensions()
+            .iter()
+            .any(|e| ext.eq_ignore_ascii_case(e))
+    }
+}
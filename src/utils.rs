@@ -1,16 +1,13 @@
-use crate::BResult;
-use std::path::PathBuf;
+use crate::{sniff::ImageFormat, walk::WalkOpt, BResult};
+use std::path::{Path, PathBuf};
 
 pub fn ims_init(
     input: &[PathBuf],
+    walk: &WalkOpt,
     output_dir: &std::path::Path,
     nproc: Option<usize>,
 ) -> BResult<Vec<PathBuf>> {
-    let mut images = input.to_owned();
-    if images[0].to_string_lossy() == "./*" {
-        images = read_cwd()?;
-        filter_images(&mut images);
-    }
+    let images = walk.expand(input)?;
     mkdir(output_dir)?;
     if let Some(n) = nproc {
         rayon::ThreadPoolBuilder::new()
@@ -32,18 +29,62 @@ pub fn mkdir(dir: &std::path::Path) -> Result<(), String> {
     Ok(())
 }
 
-pub fn read_cwd() -> Result<Vec<PathBuf>, std::io::Error> {
-    std::path::Path::new(".")
-        .read_dir()?
-        .map(|r| r.map(|d| d.path()))
-        .collect::<Result<Vec<PathBuf>, _>>()
+/// Keep files with image content, extension is ignored
+pub fn filter_images(input: &mut Vec<PathBuf>) {
+    input.retain(|i| matches!(ImageFormat::sniff(i), Ok(Some(_))));
 }
 
-pub fn filter_images(input: &mut Vec<PathBuf>) {
-    let image_formats = ["png", "jpg", "jxl", "avif", "webp"];
-    input.retain(|i| {
-        image_formats
-            .iter()
-            .any(|&format| i.extension().unwrap_or_default() == format)
-    });
+/// Open image by content, jxl and avif are decoded with external decoders
+pub fn image_open(img: &Path) -> BResult<image::DynamicImage> {
+    let img = match ImageFormat::sniff(img)? {
+        Some(ImageFormat::Jxl) => image::open(image_jxl_decode(img)?.path())?,
+        Some(ImageFormat::Avif) => image::open(image_avif_decode(img, Some(8))?.path())?,
+        _ => image::ImageReader::open(img)?
+            .with_guessed_format()?
+            .decode()?,
+    };
+    Ok(img)
+}
+
+/// Image dimensions from header, format is guessed from content
+pub fn image_dimensions(img: &Path) -> BResult<(u32, u32)> {
+    Ok(image::ImageReader::open(img)?
+        .with_guessed_format()?
+        .into_dimensions()?)
+}
+
+pub fn image_jxl_decode(i: &Path) -> BResult<tempfile::NamedTempFile> {
+    let tf_out = tempfile::Builder::new().suffix(".png").tempfile()?;
+    let outp = std::process::Command::new("djxl")
+        .arg(i)
+        .arg(tf_out.path())
+        .output()?;
+    command_print_if_error(&outp)?;
+    Ok(tf_out)
+}
+
+/// Decode avif to temporary png, with `depth` None png is 16-bit for high bit depth source
+pub fn image_avif_decode(i: &Path, depth: Option<u8>) -> BResult<tempfile::NamedTempFile> {
+    let tf_out = tempfile::Builder::new().suffix(".png").tempfile()?;
+    let mut cmd = std::process::Command::new("avifdec");
+    if let Some(depth) = depth {
+        cmd.args(["-d", &depth.to_string()]);
+    }
+    let outp = cmd
+        .args(["--png-compress", "0"])
+        .arg(i)
+        .arg(tf_out.path())
+        .output()?;
+    command_print_if_error(&outp)?;
+    Ok(tf_out)
+}
+
+pub fn command_print_if_error(output: &std::process::Output) -> std::io::Result<()> {
+    if output.status.success() {
+        Ok(())
+    } else {
+        let o = [&output.stderr, "\n".as_bytes(), &output.stdout].concat();
+        println!("{}", std::str::from_utf8(&o).unwrap());
+        Err(std::io::Error::other("Command returned error status"))
+    }
 }
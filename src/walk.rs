@@ -0,0 +1,199 @@
+use std::{
+    collections::HashSet,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use clap::{Args, ValueEnum};
+
+use crate::{sniff::ImageFormat, utils, BResult};
+
+/// How symbolic links are handled while walking directories
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symlinks {
+    /// ignore links
+    Skip,
+    /// keep links to files, don't descend into linked dirs
+    Files,
+    /// follow links to files and dirs
+    Follow,
+}
+
+// Input walking options shared by subcommands taking image lists
+#[derive(Args, Debug, Clone)]
+pub struct WalkOpt {
+    /// walk input directories recursively
+    #[arg(short = 'R', long)]
+    pub recursive: bool,
+    /// max directory depth, implies '-R'
+    #[arg(long)]
+    pub max_depth: Option<usize>,
+    /// only files matching glob, file name or path if glob has '/'
+    #[arg(long)]
+    pub include: Vec<String>,
+    /// skip files and dirs matching glob
+    #[arg(long)]
+    pub exclude: Vec<String>,
+    /// symlink policy while walking
+    #[arg(long, value_enum, default_value = "files")]
+    pub symlinks: Symlinks,
+    /// read input paths from stdin, one per line or NUL separated
+    #[arg(long)]
+    pub stdin: bool,
+}
+
+impl WalkOpt {
+    /// Expand input paths: dirs are walked for images, `./*` is the current dir.
+    /// Explicit files are kept regardless of extension
+    pub fn expand(&self, input: &[PathBuf]) -> BResult<Vec<PathBuf>> {
+        let default_input = input.len() == 1 && input[0].to_string_lossy() == "./*";
+        let mut roots = Vec::new();
+        if self.stdin {
+            let mut buf = String::new();
+            std::io::stdin().read_to_string(&mut buf)?;
+            let sep = if buf.contains('\0') { '\0' } else { '\n' };
+            roots.extend(
+                buf.split(sep)
+                    .map(|l| l.trim_end_matches('\r'))
+                    .filter(|l| !l.is_empty())
+                    .map(PathBuf::from),
+            );
+        }
+        if !default_input {
+            roots.extend(input.iter().cloned());
+        } else if !self.stdin {
+            roots.push(PathBuf::from("."));
+        }
+
+        let max_depth = match (self.max_depth, self.recursive) {
+            (Some(d), _) => d.max(1),
+            (None, true) => usize::MAX,
+            (None, false) => 1,
+        };
+        let mut images = Vec::new();
+        let mut visited = HashSet::new();
+        for root in roots {
+            if root.is_dir() {
+                self.walk(&root, max_depth, &mut visited, &mut images)?;
+            } else if !self.excluded(&root) {
+                images.push(root);
+            }
+        }
+        Ok(images)
+    }
+
+    /// Expand input paths keeping only images of `formats`, explicit files are kept
+    /// to report their format error
+    pub fn expand_formats(
+        &self,
+        input: &[PathBuf],
+        formats: &[ImageFormat],
+    ) -> BResult<Vec<PathBuf>> {
+        let mut images = self.expand(input)?;
+        images.retain(|p| {
+            input.contains(p)
+                || ImageFormat::sniff(p).is_ok_and(|f| f.is_some_and(|f| formats.contains(&f)))
+        });
+        Ok(images)
+    }
+
+    fn walk(
+        &self,
+        dir: &Path,
+        depth: usize,
+        visited: &mut HashSet<PathBuf>,
+        images: &mut Vec<PathBuf>,
+    ) -> BResult<()> {
+        // symlinked dirs may form loops
+        if !visited.insert(dir.canonicalize()?) {
+            return Ok(());
+        }
+        let mut entries = dir
+            .read_dir()?
+            .map(|r| r.map(|d| d.path()))
+            .collect::<Result<Vec<PathBuf>, _>>()?;
+        entries.sort_unstable();
+
+        let mut files = Vec::new();
+        for path in entries {
+            let meta = path.symlink_metadata()?;
+            let is_link = meta.file_type().is_symlink();
+            if is_link && self.symlinks == Symlinks::Skip {
+                continue;
+            }
+            if path.is_dir() {
+                let follow = !is_link || self.symlinks == Symlinks::Follow;
+                if follow && depth > 1 && !self.matches(&self.exclude, &path) {
+                    if let Err(e) = self.walk(&path, depth - 1, visited, images) {
+                        eprintln!("Error reading dir {}: {}", path.display(), e);
+                    }
+                }
+            } else if path.is_file() && !self.excluded(&path) {
+                files.push(path);
+            }
+        }
+        utils::filter_images(&mut files);
+        images.append(&mut files);
+        Ok(())
+    }
+
+    /// File doesn't match include globs or matches exclude globs
+    fn excluded(&self, path: &Path) -> bool {
+        (!self.include.is_empty() && !self.matches(&self.include, path))
+            || self.matches(&self.exclude, path)
+    }
+
+    fn matches(&self, globs: &[String], path: &Path) -> bool {
+        let full = path.to_string_lossy();
+        let full = full.strip_prefix("./").unwrap_or(&full);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        globs.iter().any(|g| {
+            let target = if g.contains('/') { full } else { &name };
+            glob_match(
+                &g.chars().collect::<Vec<_>>(),
+                &target.chars().collect::<Vec<_>>(),
+            )
+        })
+    }
+}
+
+/// Match glob with `*`, `**`, `?` and `[...]` classes, only `**` matches '/'
+pub fn glob_match(pat: &[char], s: &[char]) -> bool {
+    match pat {
+        [] => s.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // '**/' also matches no dirs
+            let rest_no_slash = rest.strip_prefix(&['/']).unwrap_or(rest);
+            glob_match(rest_no_slash, s) || (0..=s.len()).any(|i| glob_match(rest, &s[i..]))
+        }
+        ['*', rest @ ..] => (0..=s.len())
+            .take_while(|&i| i == 0 || s[i - 1] != '/')
+            .any(|i| glob_match(rest, &s[i..])),
+        ['?', rest @ ..] => matches!(s, [c, ..] if *c != '/') && glob_match(rest, &s[1..]),
+        ['[', rest @ ..] => {
+            let Some(end) = rest.iter().skip(1).position(|&c| c == ']').map(|p| p + 1) else {
+                return s.first() == Some(&'[') && glob_match(rest, &s[1..]);
+            };
+            let Some(&c) = s.first() else {
+                return false;
+            };
+            let (negate, class) = match &rest[..end] {
+                ['!' | '^', class @ ..] => (true, class),
+                class => (false, class),
+            };
+            let mut found = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == '-' {
+                    found |= (class[i]..=class[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    found |= class[i] == c;
+                    i += 1;
+                }
+            }
+            found != negate && c != '/' && glob_match(&rest[end + 1..], &s[1..])
+        }
+        [p, rest @ ..] => s.first() == Some(p) && glob_match(rest, &s[1..]),
+    }
+}